@@ -0,0 +1,378 @@
+use crate::domain::{ClientId, Transaction, TransactionRow};
+use crate::engine::{ClientAccount, PaymentsEngine, ProcessingError};
+use csv::{ReaderBuilder, StringRecord};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_stream::{Stream, StreamExt};
+
+const COMMAND_BUFFER_SIZE: usize = 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum SubmitError {
+    Rejected(ProcessingError),
+    EngineStopped,
+}
+
+impl Display for SubmitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubmitError::Rejected(e) => write!(f, "Transaction was rejected: {e}"),
+            SubmitError::EngineStopped => write!(f, "The engine task is no longer running"),
+        }
+    }
+}
+
+enum Command {
+    Process(Transaction, oneshot::Sender<Result<(), ProcessingError>>),
+    /// Counts a malformed input row, see [`PaymentsEngine::skip_row`]
+    SkipRow,
+    ClientAccounts(oneshot::Sender<HashMap<ClientId, ClientAccount>>),
+}
+
+/// Cloneable handle to a [`PaymentsEngine`] owned by a dedicated actor task.
+///
+/// Commands from all handles are applied one at a time, in the order they reach the actor.
+/// The actor stops once every handle has been dropped, and its [`JoinHandle`] yields the engine.
+#[derive(Clone)]
+pub struct EngineHandle {
+    sender: mpsc::Sender<Command>,
+}
+
+impl EngineHandle {
+    /// Spawns the actor task on the current tokio runtime.
+    pub fn spawn(engine: PaymentsEngine) -> (Self, JoinHandle<PaymentsEngine>) {
+        let (sender, receiver) = mpsc::channel(COMMAND_BUFFER_SIZE);
+        let task = tokio::spawn(run_engine(engine, receiver));
+
+        (Self { sender }, task)
+    }
+
+    pub async fn process_transaction(&self, transaction: Transaction) -> Result<(), SubmitError> {
+        let (reply, response) = oneshot::channel();
+        self.sender
+            .send(Command::Process(transaction, reply))
+            .await
+            .map_err(|_| SubmitError::EngineStopped)?;
+
+        response
+            .await
+            .map_err(|_| SubmitError::EngineStopped)?
+            .map_err(SubmitError::Rejected)
+    }
+
+    /// Returns a snapshot of all client accounts at the time the request is handled.
+    pub async fn client_accounts(&self) -> Result<HashMap<ClientId, ClientAccount>, SubmitError> {
+        let (reply, response) = oneshot::channel();
+        self.sender
+            .send(Command::ClientAccounts(reply))
+            .await
+            .map_err(|_| SubmitError::EngineStopped)?;
+
+        response.await.map_err(|_| SubmitError::EngineStopped)
+    }
+
+    /// Submits every transaction of the stream in order. Rejected transactions are logged and skipped.
+    pub async fn process_stream(
+        &self,
+        transactions: impl Stream<Item = Transaction>,
+    ) -> Result<(), SubmitError> {
+        tokio::pin!(transactions);
+        while let Some(transaction) = transactions.next().await {
+            self.submit_and_log(transaction).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Async counterpart of [`crate::csv::process_csv_transactions`].
+    pub async fn process_csv(&self, input: impl AsyncRead + Unpin) -> Result<(), io::Error> {
+        let mut lines = BufReader::new(input).lines();
+
+        let Some(header_line) = lines.next_line().await? else {
+            return Ok(());
+        };
+        let headers = match parse_csv_line(&header_line) {
+            Some(Ok(headers)) => headers,
+            Some(Err(e)) => return Err(e.into()),
+            None => return Ok(()),
+        };
+
+        while let Some(line) = lines.next_line().await? {
+            let row = match parse_csv_line(&line) {
+                Some(Ok(record)) => record.deserialize::<TransactionRow>(Some(&headers)),
+                Some(Err(e)) => Err(e),
+                None => continue,
+            };
+
+            match row {
+                Ok(transaction) => self.submit_and_log(transaction.into()).await?,
+                Err(e) => {
+                    eprintln!("An error occurred while deserializing a row: {e}");
+                    self.skip_row().await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Processes newline-delimited JSON, one transaction object per line.
+    pub async fn process_jsonl(&self, input: impl AsyncRead + Unpin) -> Result<(), io::Error> {
+        let mut lines = BufReader::new(input).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str::<TransactionRow>(&line) {
+                Ok(transaction) => self.submit_and_log(transaction.into()).await?,
+                Err(e) => {
+                    eprintln!("An error occurred while deserializing a row: {e}");
+                    self.skip_row().await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn skip_row(&self) -> Result<(), SubmitError> {
+        self.sender
+            .send(Command::SkipRow)
+            .await
+            .map_err(|_| SubmitError::EngineStopped)
+    }
+
+    async fn submit_and_log(&self, transaction: Transaction) -> Result<(), SubmitError> {
+        match self.process_transaction(transaction).await {
+            Err(SubmitError::Rejected(e)) => {
+                eprintln!("An error occurred while processing a transaction: {e}");
+                Ok(())
+            }
+            other => other,
+        }
+    }
+}
+
+impl std::error::Error for SubmitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SubmitError::Rejected(e) => Some(e),
+            SubmitError::EngineStopped => None,
+        }
+    }
+}
+
+impl From<SubmitError> for io::Error {
+    fn from(value: SubmitError) -> Self {
+        io::Error::other(value.to_string())
+    }
+}
+
+async fn run_engine(
+    mut engine: PaymentsEngine,
+    mut receiver: mpsc::Receiver<Command>,
+) -> PaymentsEngine {
+    while let Some(command) = receiver.recv().await {
+        match command {
+            Command::Process(transaction, reply) => {
+                // The submitter may have given up waiting, the transaction is applied regardless
+                let _ = reply.send(engine.process_transaction(transaction));
+            }
+            Command::SkipRow => engine.skip_row(),
+            Command::ClientAccounts(reply) => {
+                let _ = reply.send(engine.client_accounts().clone());
+            }
+        }
+    }
+
+    engine
+}
+
+/// Parses a single CSV line, with the same settings as the synchronous reader.
+fn parse_csv_line(line: &str) -> Option<Result<StringRecord, csv::Error>> {
+    let mut csv_reader = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(line.as_bytes());
+
+    let mut record = StringRecord::new();
+    match csv_reader.read_record(&mut record) {
+        Ok(true) => Some(Ok(record)),
+        Ok(false) => None,
+        Err(e) => Some(Err(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::TransactionId;
+    use crate::domain::TransactionType::{Deposit, Withdrawal};
+    use crate::test_utils::create_transaction;
+    use rust_decimal::{Decimal, dec};
+
+    #[tokio::test]
+    async fn test_process_transaction_and_query_accounts() {
+        let (handle, _task) = EngineHandle::spawn(PaymentsEngine::new());
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        handle.process_transaction(deposit).await.unwrap();
+
+        let accounts = handle.client_accounts().await.unwrap();
+        let account = accounts.get(&ClientId::new(1)).unwrap();
+        assert_eq!(account.available_balance, Decimal::TEN);
+    }
+
+    #[tokio::test]
+    async fn test_process_transaction_rejection() {
+        let (handle, _task) = EngineHandle::spawn(PaymentsEngine::new());
+
+        let withdrawal = create_transaction(Withdrawal, 1, 1, Some(Decimal::TEN));
+        let result = handle.process_transaction(withdrawal).await;
+
+        assert_eq!(
+            result,
+            Err(SubmitError::Rejected(ProcessingError::InsufficientFunds {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1),
+                requested: Decimal::TEN,
+                available: Decimal::ZERO,
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_stream() {
+        let (handle, task) = EngineHandle::spawn(PaymentsEngine::new());
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Withdrawal, 1, 2, Some(dec!(20))),
+            create_transaction(Withdrawal, 1, 3, Some(dec!(4))),
+        ];
+        handle
+            .process_stream(tokio_stream::iter(transactions))
+            .await
+            .unwrap();
+        drop(handle);
+
+        let engine = task.await.unwrap();
+        let account = engine.client_accounts().get(&ClientId::new(1)).unwrap();
+        assert_eq!(account.available_balance, dec!(6));
+    }
+
+    #[tokio::test]
+    async fn test_process_csv() {
+        let (handle, _task) = EngineHandle::spawn(PaymentsEngine::new());
+        let csv_data = "type, client, tx, amount
+deposit, 1, 1, 1.0
+invalid, 1, 2, 1.0
+deposit, 2, 3, 2.0
+withdrawal, 1, 4, 0.5
+dispute, 2, 3,
+";
+
+        handle.process_csv(csv_data.as_bytes()).await.unwrap();
+
+        let accounts = handle.client_accounts().await.unwrap();
+        assert_eq!(accounts.len(), 2);
+        let account1 = accounts.get(&ClientId::new(1)).unwrap();
+        assert_eq!(account1.available_balance, dec!(0.5));
+        let account2 = accounts.get(&ClientId::new(2)).unwrap();
+        assert_eq!(account2.available_balance, Decimal::ZERO);
+        assert_eq!(account2.held_balance, dec!(2));
+    }
+
+    #[tokio::test]
+    async fn test_process_csv_empty_input() {
+        let (handle, _task) = EngineHandle::spawn(PaymentsEngine::new());
+
+        handle.process_csv("".as_bytes()).await.unwrap();
+
+        assert!(handle.client_accounts().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_state_at_counts_malformed_rows() {
+        let csv_data = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,one,2,1.0
+withdrawal,1,3,3.0
+";
+        let jsonl_data = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "10.0"}
+{"type": "deposit", "client": "one", "tx": 2, "amount": "1.0"}
+{"type": "withdrawal", "client": 1, "tx": 3, "amount": "3.0"}
+"#;
+
+        for jsonl in [false, true] {
+            let (handle, task) = EngineHandle::spawn(PaymentsEngine::new());
+            if jsonl {
+                handle.process_jsonl(jsonl_data.as_bytes()).await.unwrap();
+            } else {
+                handle.process_csv(csv_data.as_bytes()).await.unwrap();
+            }
+            drop(handle);
+            let engine = task.await.unwrap();
+
+            assert_eq!(engine.sequence(), 3);
+            let available_at = |sequence| {
+                engine.state_at(sequence).unwrap().client_accounts()[&ClientId::new(1)]
+                    .available_balance
+            };
+            assert_eq!(available_at(2), Decimal::TEN);
+            assert_eq!(available_at(3), dec!(7));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_jsonl() {
+        let (handle, _task) = EngineHandle::spawn(PaymentsEngine::new());
+        let jsonl_data = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+{"type": "deposit", "client": 1, "tx": 2, "amount": "-1.0"}
+
+{"type": "deposit", "client": 1, "tx": 3, "amount": "0.5"}
+{"type": "dispute", "client": 1, "tx": 3}
+"#;
+
+        handle.process_jsonl(jsonl_data.as_bytes()).await.unwrap();
+
+        let accounts = handle.client_accounts().await.unwrap();
+        let account = accounts.get(&ClientId::new(1)).unwrap();
+        assert_eq!(account.available_balance, dec!(1.5));
+        assert_eq!(account.held_balance, dec!(0.5));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_handles() {
+        let (handle, task) = EngineHandle::spawn(PaymentsEngine::new());
+
+        let mut submitters = Vec::new();
+        for client in 1..=4u16 {
+            let handle = handle.clone();
+            submitters.push(tokio::spawn(async move {
+                for tx in 0..25u32 {
+                    let tx_id = u32::from(client) * 1000 + tx;
+                    let deposit = create_transaction(Deposit, client, tx_id, Some(Decimal::ONE));
+                    handle.process_transaction(deposit).await.unwrap();
+                }
+            }));
+        }
+        for submitter in submitters {
+            submitter.await.unwrap();
+        }
+        drop(handle);
+
+        let engine = task.await.unwrap();
+        assert_eq!(engine.client_accounts().len(), 4);
+        for account in engine.client_accounts().values() {
+            assert_eq!(account.available_balance, dec!(25));
+        }
+    }
+}
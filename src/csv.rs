@@ -1,37 +1,590 @@
-use crate::domain::{ClientAccountOutput, TransactionRow};
+use crate::domain::{
+    ClientAccountOutput, CollectionOutput, Transaction, TransactionRow, TransactionType,
+};
 use crate::engine::PaymentsEngine;
-use csv::{ReaderBuilder, Writer};
+use crate::events::Event;
+use crate::fees::FeeEntry;
+use crate::interest::InterestEntry;
+use crate::report::{AccountDiff, DiffSide, TransactionStats};
+use clap::ValueEnum;
+use csv::{Position, ReaderBuilder, StringRecord, Writer};
+use csv_core::ReadRecordResult;
+use serde::Deserialize;
+use serde::de::{IntoDeserializer, value};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
 use std::io;
+use std::io::Cursor;
+use std::num::NonZeroUsize;
+use std::thread;
+
+/// Processes every row of the input, reporting the malformed and rejected ones on stderr. Fails if
+/// the input can't be read, e.g. when a compressed input is corrupt or truncated, leaving the rows
+/// read so far applied.
+pub fn process_csv_transactions(
+    engine: &mut PaymentsEngine,
+    input: impl io::Read,
+) -> Result<(), io::Error> {
+    let mut csv_reader = ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(input);
+    read_headers(&mut csv_reader)?;
 
-pub fn process_csv_transactions(engine: &mut PaymentsEngine, input: impl io::Read) {
+    for result in csv_reader.deserialize::<TransactionRow>() {
+        match result {
+            Ok(transaction) => {
+                if let Err(e) = engine.process_transaction(transaction.into()) {
+                    eprintln!("An error occurred while processing a transaction: {e}");
+                }
+            }
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => {
+                eprintln!("An error occurred while deserializing a row: {e}");
+                engine.skip_row();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the header row up front, as deserializing rows would otherwise take an input that can't
+/// be read for an empty one.
+fn read_headers<R: io::Read>(csv_reader: &mut csv::Reader<R>) -> Result<(), io::Error> {
+    match csv_reader.headers() {
+        Err(e) if e.is_io_error() => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Row that [`process_csv_transactions_strict`] refused, with its position in the input.
+#[derive(Debug)]
+pub struct InputError {
+    /// Line of the row, counting from 1 with the header
+    pub line: u64,
+    /// Column of the offending field, counting from 1, if the error is about a single field
+    pub column: Option<u64>,
+    pub kind: InputErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputErrorKind {
+    /// The row couldn't be read or a field couldn't be parsed, e.g. a client that isn't a number
+    Malformed(String),
+    UnknownTransactionType(String),
+    /// A deposit, withdrawal, authorization or refund without an amount
+    MissingAmount(TransactionType),
+}
+
+impl Display for InputError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}", self.line)?;
+        if let Some(column) = self.column {
+            write!(f, ", column {column}")?;
+        }
+        match &self.kind {
+            InputErrorKind::Malformed(reason) => write!(f, ": malformed row: {reason}"),
+            InputErrorKind::UnknownTransactionType(tx_type) => {
+                write!(f, ": unknown transaction type {tx_type:?}")
+            }
+            InputErrorKind::MissingAmount(tx_type) => write!(f, ": {tx_type} without an amount"),
+        }
+    }
+}
+
+impl std::error::Error for InputError {}
+
+/// Processes the input like [`process_csv_transactions`], but refuses the whole input if any row
+/// is malformed, has an unknown type or lacks a required amount. Every row is parsed before any of
+/// them is applied, so on error the engine is left as it was before the call.
+///
+/// Transactions that are parsed but rejected by the engine, e.g. for insufficient funds, don't
+/// fail the input.
+pub fn process_csv_transactions_strict(
+    engine: &mut PaymentsEngine,
+    input: impl io::Read,
+) -> Result<(), InputError> {
+    for transaction in parse_csv_transactions_strict(input)? {
+        if let Err(e) = engine.process_transaction(transaction) {
+            eprintln!("An error occurred while processing a transaction: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_csv_transactions_strict(input: impl io::Read) -> Result<Vec<Transaction>, InputError> {
     let mut csv_reader = ReaderBuilder::new()
         .has_headers(true)
         .flexible(true)
         .trim(csv::Trim::All)
         .from_reader(input);
+    let headers = csv_reader.headers().map_err(|e| malformed(&e))?.clone();
+    let column_of = |name: &str| headers.iter().position(|header| header == name);
+    let (type_column, amount_column) = (column_of("type"), column_of("amount"));
+
+    let mut transactions = Vec::new();
+    for result in csv_reader.records() {
+        let record = result.map_err(|e| malformed(&e))?;
+        let line = record.position().map_or(0, Position::line);
+
+        let row: TransactionRow = match record.deserialize(Some(&headers)) {
+            Ok(row) => row,
+            Err(e) => {
+                // Serde doesn't report the field of unknown variants, so the type is checked alone
+                let tx_type = type_column.and_then(|column| record.get(column));
+                if let Some(tx_type) = tx_type
+                    && parse_transaction_type(tx_type).is_err()
+                {
+                    return Err(InputError {
+                        line,
+                        column: type_column.map(|column| column as u64 + 1),
+                        kind: InputErrorKind::UnknownTransactionType(tx_type.to_string()),
+                    });
+                }
+
+                let column = match e.kind() {
+                    csv::ErrorKind::Deserialize { err, .. } => err.field(),
+                    _ => None,
+                };
+                return Err(InputError {
+                    line,
+                    column: column.map(|column| column + 1),
+                    kind: InputErrorKind::Malformed(deserialize_reason(&e)),
+                });
+            }
+        };
+        if row.amount.is_none() && row.tx_type.requires_amount() {
+            return Err(InputError {
+                line,
+                column: amount_column.map(|column| column as u64 + 1),
+                kind: InputErrorKind::MissingAmount(row.tx_type),
+            });
+        }
+
+        transactions.push(row.into());
+    }
+
+    Ok(transactions)
+}
+
+pub(crate) fn parse_transaction_type(tx_type: &str) -> Result<TransactionType, value::Error> {
+    TransactionType::deserialize(tx_type.into_deserializer())
+}
+
+fn malformed(e: &csv::Error) -> InputError {
+    InputError {
+        line: e.position().map_or(0, Position::line),
+        column: None,
+        kind: InputErrorKind::Malformed(e.to_string()),
+    }
+}
+
+/// Reason of a deserialization error, without the position that [`InputError`] already has.
+fn deserialize_reason(e: &csv::Error) -> String {
+    match e.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => err.kind().to_string(),
+        _ => e.to_string(),
+    }
+}
+
+/// Parses every row without processing it, and returns the errors of the malformed ones. Fails if
+/// the input can't be read, like [`process_csv_transactions`].
+pub fn validate_csv_transactions(input: impl io::Read) -> Result<Vec<csv::Error>, io::Error> {
+    let mut csv_reader = ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(input);
+    read_headers(&mut csv_reader)?;
+
+    let mut errors = Vec::new();
+    for result in csv_reader.deserialize::<TransactionRow>() {
+        match result {
+            Ok(_) => {}
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    Ok(errors)
+}
+
+/// Processes the input like [`process_csv_transactions`], adding statistics about every row to
+/// `stats`, so that they can be collected over several inputs.
+pub fn collect_csv_stats(
+    engine: &mut PaymentsEngine,
+    input: impl io::Read,
+    stats: &mut TransactionStats,
+) -> Result<(), io::Error> {
+    let mut csv_reader = ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(input);
+    read_headers(&mut csv_reader)?;
 
     for result in csv_reader.deserialize::<TransactionRow>() {
+        match result {
+            Ok(row) => {
+                let transaction: Transaction = row.into();
+                let events = engine.events().len();
+                let result = engine.process_transaction(transaction.clone());
+                let applied =
+                    engine.events()[events..]
+                        .iter()
+                        .find_map(|record| match &record.event {
+                            Event::TransactionApplied { transaction, .. } => Some(transaction),
+                            _ => None,
+                        });
+                stats.record(&transaction, &result, applied);
+            }
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(_) => {
+                stats.record_malformed();
+                engine.skip_row();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Compares two account output files client by client, matching columns by name. Differences are
+/// returned in client order. A client with several rows in one file is reported as a
+/// [`AccountDiff::Duplicate`] for each extra row.
+pub fn diff_account_records(
+    left: impl io::Read,
+    right: impl io::Read,
+) -> Result<Vec<AccountDiff>, csv::Error> {
+    let mut diffs = Vec::new();
+    let left = read_account_records(left, DiffSide::Left, &mut diffs)?;
+    let mut right = read_account_records(right, DiffSide::Right, &mut diffs)?;
+
+    for (client, left_record) in left {
+        let Some(right_record) = right.remove(&client) else {
+            diffs.push((
+                client.clone(),
+                AccountDiff::Removed {
+                    client,
+                    record: left_record.line,
+                },
+            ));
+            continue;
+        };
+
+        let mut columns: Vec<&String> = left_record.values.keys().collect();
+        columns.extend(
+            right_record
+                .values
+                .keys()
+                .filter(|column| !left_record.values.contains_key(*column)),
+        );
+        for column in columns {
+            let left_value = left_record.values.get(column).cloned().unwrap_or_default();
+            let right_value = right_record.values.get(column).cloned().unwrap_or_default();
+            if left_value != right_value {
+                diffs.push((
+                    client.clone(),
+                    AccountDiff::Changed {
+                        client: client.clone(),
+                        column: column.clone(),
+                        left: left_value,
+                        right: right_value,
+                    },
+                ));
+            }
+        }
+    }
+    for (client, right_record) in right {
+        diffs.push((
+            client.clone(),
+            AccountDiff::Added {
+                client,
+                record: right_record.line,
+            },
+        ));
+    }
+
+    // Clients are compared numerically when they can be
+    diffs.sort_by_key(|(client, _)| (client.parse::<u64>().ok(), client.clone()));
+    Ok(diffs.into_iter().map(|(_, diff)| diff).collect())
+}
+
+struct AccountRecord {
+    line: String,
+    values: BTreeMap<String, String>,
+}
+
+/// Reads the first row of every client, and adds the other rows of a client to `diffs` as
+/// duplicates.
+fn read_account_records(
+    input: impl io::Read,
+    side: DiffSide,
+    diffs: &mut Vec<(String, AccountDiff)>,
+) -> Result<HashMap<String, AccountRecord>, csv::Error> {
+    let mut csv_reader = ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(input);
+    let headers = csv_reader.headers()?.clone();
+
+    let mut records = HashMap::new();
+    for result in csv_reader.records() {
+        let record = result?;
+        let values: BTreeMap<String, String> = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(column, value)| (column.to_string(), value.to_string()))
+            .collect();
+        let client = values.get("client").cloned().unwrap_or_default();
+        let line = record.iter().collect::<Vec<_>>().join(",");
+        match records.entry(client) {
+            Entry::Vacant(entry) => {
+                entry.insert(AccountRecord { line, values });
+            }
+            Entry::Occupied(entry) => diffs.push((
+                entry.key().clone(),
+                AccountDiff::Duplicate {
+                    client: entry.key().clone(),
+                    side,
+                    record: line,
+                },
+            )),
+        }
+    }
+
+    Ok(records)
+}
+
+/// Parses the input on `workers` threads and then dispatches the parsed transactions to the engine
+/// in file order, so that the result is the same as with [`process_csv_transactions`].
+///
+/// The whole input is buffered in memory and split into chunks at record boundaries, found by a
+/// first pass of the CSV tokenizer, so quoted fields spanning multiple lines and any line
+/// terminator are handled like in sequential mode.
+pub fn process_csv_transactions_parallel(
+    engine: &mut PaymentsEngine,
+    mut input: impl io::Read,
+    workers: NonZeroUsize,
+) -> Result<(), io::Error> {
+    let mut buffer = Vec::new();
+    input.read_to_end(&mut buffer)?;
+
+    let buffer = buffer.as_slice();
+    let chunks = split_into_chunks(&record_ends(buffer), workers.get());
+    let parsed_chunks: Vec<Vec<Result<TransactionRow, csv::Error>>> = thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .iter()
+            .map(|&(start, end)| scope.spawn(move || parse_chunk(buffer, start, end)))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("CSV parser thread panicked"))
+            .collect()
+    });
+
+    for result in parsed_chunks.into_iter().flatten() {
         match result {
             Ok(transaction) => {
                 if let Err(e) = engine.process_transaction(transaction.into()) {
-                    eprintln!("An error occurred while processing a transaction: {e:?}");
+                    eprintln!("An error occurred while processing a transaction: {e}");
                 }
             }
             Err(e) => {
                 eprintln!("An error occurred while deserializing a row: {e}");
+                engine.skip_row();
             }
         }
     }
+
+    Ok(())
+}
+
+/// Offsets right after the end of each record of the input, the headers included.
+fn record_ends(buffer: &[u8]) -> Vec<usize> {
+    let mut reader = csv_core::Reader::new();
+    let mut output = [0; 1024];
+    let mut ends = [0; 16];
+
+    let mut record_ends = Vec::new();
+    let mut position = 0;
+    loop {
+        let (result, read, _, _) = reader.read_record(&buffer[position..], &mut output, &mut ends);
+        position += read;
+        match result {
+            // Reading an empty slice once the whole buffer was consumed signals the end of input
+            ReadRecordResult::InputEmpty
+            | ReadRecordResult::OutputFull
+            | ReadRecordResult::OutputEndsFull => {}
+            ReadRecordResult::Record => record_ends.push(position),
+            ReadRecordResult::End => return record_ends,
+        }
+    }
+}
+
+/// Splits the records after the headers into at most `workers` byte ranges of whole records.
+fn split_into_chunks(record_ends: &[usize], workers: usize) -> Vec<(usize, usize)> {
+    let [body_start, .., body_end] = *record_ends else {
+        // Empty input or headers only
+        return Vec::new();
+    };
+    let target_size = (body_end - body_start).div_ceil(workers).max(1);
+
+    let mut chunks = Vec::with_capacity(workers);
+    let mut start = body_start;
+    for &end in record_ends {
+        if end - start >= target_size {
+            chunks.push((start, end));
+            start = end;
+        }
+    }
+    if start < body_end {
+        chunks.push((start, body_end));
+    }
+
+    chunks
+}
+
+fn parse_chunk(buffer: &[u8], start: usize, end: usize) -> Vec<Result<TransactionRow, csv::Error>> {
+    let mut csv_reader = ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(Cursor::new(buffer));
+
+    let headers = match csv_reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(e) => return vec![Err(e)],
+    };
+
+    // Keep line numbers in error messages relative to the whole file
+    let line = buffer[..start].iter().filter(|&&b| b == b'\n').count() as u64 + 1;
+    let mut position = Position::new();
+    position
+        .set_byte(start as u64)
+        .set_line(line)
+        .set_record(line - 1);
+    if let Err(e) = csv_reader.seek(position) {
+        return vec![Err(e)];
+    }
+
+    let mut rows = Vec::new();
+    let mut record = StringRecord::new();
+    while csv_reader.position().byte() < end as u64 {
+        match csv_reader.read_record(&mut record) {
+            Ok(true) => rows.push(record.deserialize::<TransactionRow>(Some(&headers))),
+            Ok(false) => break,
+            Err(e) => rows.push(Err(e)),
+        }
+    }
+
+    rows
+}
+
+/// Order of the rows of the account output.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputOrder {
+    /// By ascending client id, so that the same input always gives the same output
+    #[default]
+    Client,
+    /// By descending total balance, then by ascending client id
+    Total,
+    /// In whatever order the accounts are stored, which changes between runs
+    Unsorted,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    /// Adds a `fees` column with the total fees charged to each account
+    pub include_fees: bool,
+    /// Adds an `overdraft` column with how much of its credit line each account is using
+    pub include_overdraft: bool,
+    pub order: OutputOrder,
 }
 
 pub fn print_account_records(
     engine: &PaymentsEngine,
     output: impl io::Write,
 ) -> Result<(), io::Error> {
-    let client_accounts = engine.client_accounts();
+    print_account_records_with_options(engine, output, &OutputOptions::default())
+}
+
+pub fn print_account_records_with_options(
+    engine: &PaymentsEngine,
+    output: impl io::Write,
+    options: &OutputOptions,
+) -> Result<(), io::Error> {
+    let mut client_accounts: Vec<_> = engine.client_accounts().iter().collect();
+    match options.order {
+        OutputOrder::Client => client_accounts.sort_unstable_by_key(|(client, _)| **client),
+        OutputOrder::Total => client_accounts.sort_unstable_by(|(a, a_account), (b, b_account)| {
+            b_account.total().cmp(&a_account.total()).then(a.cmp(b))
+        }),
+        OutputOrder::Unsorted => {}
+    }
+
     let mut writer = Writer::from_writer(output);
     for (client_id, account) in client_accounts {
-        writer.serialize::<ClientAccountOutput>((client_id, account).into())?;
+        let mut record = ClientAccountOutput::new(*client_id, account, *engine.precision());
+        if options.include_fees {
+            record = record.with_fees(account.fees);
+        }
+        if options.include_overdraft {
+            record = record.with_overdraft(account.overdraft_used());
+        }
+        writer.serialize(record)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes every fee charged, one row per fee entry, in the order they were booked.
+pub fn print_fee_entries(engine: &PaymentsEngine, output: impl io::Write) -> Result<(), io::Error> {
+    let mut writer = Writer::from_writer(output);
+    for entry in engine.fee_entries() {
+        writer.serialize(FeeEntry {
+            amount: engine.precision().round_padded(entry.amount),
+            ..entry.clone()
+        })?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes every interest posting, e.g. to include them in statements.
+pub fn print_interest_entries(
+    engine: &PaymentsEngine,
+    output: impl io::Write,
+) -> Result<(), io::Error> {
+    let mut writer = Writer::from_writer(output);
+    for entry in engine.interest_entries() {
+        writer.serialize(InterestEntry {
+            amount: engine.precision().round_padded(entry.amount),
+            ..entry.clone()
+        })?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes the locked accounts that owe money, with how much each of them owes, by client id.
+pub fn print_collections(engine: &PaymentsEngine, output: impl io::Write) -> Result<(), io::Error> {
+    let mut collections: Vec<_> = engine.collections().collect();
+    collections.sort_unstable_by_key(|(client, _)| **client);
+
+    let mut writer = Writer::from_writer(output);
+    for (client_id, account) in collections {
+        let record = CollectionOutput::new(*client_id, account, *engine.precision());
+        writer.serialize(record)?;
     }
     writer.flush()?;
 
@@ -41,32 +594,25 @@ pub fn print_account_records(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::domain::TransactionType::{Chargeback, Deposit, Dispute};
-    use crate::domain::{
-        Amount, ClientId, Transaction, TransactionId, TransactionStatus, TransactionType,
-    };
-    use rust_decimal::{dec, Decimal};
-    use std::io::Cursor;
+    use crate::domain::ClientId;
+    use crate::domain::TransactionType::{Chargeback, Deposit, Dispute, Withdrawal};
+    use crate::engine::NegativeBalancePolicy;
+    use crate::fees::{FeeRule, FeeSchedule};
+    use crate::input::decompress;
+    use crate::interest::{Date, InterestConfig, InterestPeriod};
+    use crate::limits::{LimitConfig, Limits};
+    use crate::precision::{Precision, Rounding};
+    use crate::test_utils::create_transaction;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use rust_decimal::{Decimal, dec};
+    use std::io::{Cursor, Write};
+    use std::path::Path;
 
     fn create_test_csv(data: &str) -> Cursor<Vec<u8>> {
         Cursor::new(data.as_bytes().to_vec())
     }
 
-    fn create_transaction(
-        tx_type: TransactionType,
-        client: u16,
-        tx_id: u32,
-        amount: Option<Decimal>,
-    ) -> Transaction {
-        Transaction {
-            tx_type,
-            client: ClientId::new(client),
-            tx: TransactionId::new(tx_id),
-            amount: amount.map(|a| Amount::new(a).unwrap()),
-            tx_status: TransactionStatus::Pending,
-        }
-    }
-
     fn create_engine_with_account() -> PaymentsEngine {
         let mut engine = PaymentsEngine::new();
 
@@ -82,7 +628,7 @@ mod tests {
         let csv_data = "type,client,tx,amount\ndeposit,1,1,1.0";
         let input = create_test_csv(csv_data);
 
-        process_csv_transactions(&mut engine, input);
+        process_csv_transactions(&mut engine, input).unwrap();
 
         let accounts = engine.client_accounts();
         assert_eq!(accounts.len(), 1);
@@ -96,7 +642,7 @@ mod tests {
         let csv_data = "type,client,tx,amount\nwithdrawal,1,2,0.5";
         let input = create_test_csv(csv_data);
 
-        process_csv_transactions(&mut engine, input);
+        process_csv_transactions(&mut engine, input).unwrap();
 
         let accounts = engine.client_accounts();
         let account = accounts.get(&ClientId::new(1)).unwrap();
@@ -110,7 +656,7 @@ mod tests {
             "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\nwithdrawal,1,3,0.5";
         let input = create_test_csv(csv_data);
 
-        process_csv_transactions(&mut engine, input);
+        process_csv_transactions(&mut engine, input).unwrap();
 
         let accounts = engine.client_accounts();
         assert_eq!(accounts.len(), 2);
@@ -128,7 +674,7 @@ mod tests {
         let csv_data = "type,client,tx,amount\ndeposit,1,1,1.0\ndispute,1,1,\nresolve,1,1,";
         let input = create_test_csv(csv_data);
 
-        process_csv_transactions(&mut engine, input);
+        process_csv_transactions(&mut engine, input).unwrap();
 
         let accounts = engine.client_accounts();
         let account = accounts.get(&ClientId::new(1)).unwrap();
@@ -142,7 +688,7 @@ mod tests {
         let csv_data = "type,client,tx,amount\ndeposit,1,1,1.0\ndispute,1,1,\nchargeback,1,1,";
         let input = create_test_csv(csv_data);
 
-        process_csv_transactions(&mut engine, input);
+        process_csv_transactions(&mut engine, input).unwrap();
 
         let accounts = engine.client_accounts();
         let account = accounts.get(&ClientId::new(1)).unwrap();
@@ -157,7 +703,7 @@ mod tests {
         let csv_data = "";
         let input = create_test_csv(csv_data);
 
-        process_csv_transactions(&mut engine, input);
+        process_csv_transactions(&mut engine, input).unwrap();
 
         let accounts = engine.client_accounts();
         assert_eq!(accounts.len(), 0);
@@ -169,7 +715,7 @@ mod tests {
         let csv_data = "type,client,tx,amount";
         let input = create_test_csv(csv_data);
 
-        process_csv_transactions(&mut engine, input);
+        process_csv_transactions(&mut engine, input).unwrap();
 
         let accounts = engine.client_accounts();
         assert_eq!(accounts.len(), 0);
@@ -181,7 +727,7 @@ mod tests {
         let csv_data = "type,client,tx,amount\n  deposit  , 1 , 1 , 1.0  ";
         let input = create_test_csv(csv_data);
 
-        process_csv_transactions(&mut engine, input);
+        process_csv_transactions(&mut engine, input).unwrap();
 
         let accounts = engine.client_accounts();
         assert_eq!(accounts.len(), 1);
@@ -195,7 +741,7 @@ mod tests {
         let csv_data = "type,client,tx,amount\ndeposit,1,1,";
         let input = create_test_csv(csv_data);
 
-        process_csv_transactions(&mut engine, input);
+        process_csv_transactions(&mut engine, input).unwrap();
 
         let accounts = engine.client_accounts();
         assert_eq!(accounts.len(), 1);
@@ -209,7 +755,7 @@ mod tests {
         let csv_data = "type,client,tx,amount\ninvalid,1,1,1.0";
         let input = create_test_csv(csv_data);
 
-        process_csv_transactions(&mut engine, input);
+        process_csv_transactions(&mut engine, input).unwrap();
 
         let accounts = engine.client_accounts();
         assert_eq!(accounts.len(), 0);
@@ -221,7 +767,7 @@ mod tests {
         let csv_data = "type,client,tx,amount\ndeposit,1,1,-1.0";
         let input = create_test_csv(csv_data);
 
-        process_csv_transactions(&mut engine, input);
+        process_csv_transactions(&mut engine, input).unwrap();
 
         let accounts = engine.client_accounts();
         assert_eq!(accounts.len(), 0);
@@ -233,21 +779,227 @@ mod tests {
         let csv_data = "type,client,tx,amount\ndeposit,1,1,1.2345";
         let input = create_test_csv(csv_data);
 
-        process_csv_transactions(&mut engine, input);
+        process_csv_transactions(&mut engine, input).unwrap();
 
         let accounts = engine.client_accounts();
         let account = accounts.get(&ClientId::new(1)).unwrap();
         assert_eq!(account.available_balance, dec!(1.2345));
     }
 
+    #[test]
+    fn test_process_csv_parallel_matches_sequential() {
+        let csv_data = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,2,2,2.0
+deposit,3,3,3.0
+deposit,1,4,2.0
+withdrawal,1,5,1.5
+dispute,2,2,
+withdrawal,3,6,1.0
+resolve,2,2,
+deposit,2,7,0.5
+dispute,3,3,
+chargeback,3,3,";
+
+        let mut sequential = PaymentsEngine::new();
+        process_csv_transactions(&mut sequential, create_test_csv(csv_data)).unwrap();
+
+        for workers in 1..=8 {
+            let mut parallel = PaymentsEngine::new();
+            process_csv_transactions_parallel(
+                &mut parallel,
+                create_test_csv(csv_data),
+                NonZeroUsize::new(workers).unwrap(),
+            )
+            .unwrap();
+
+            let expected = sequential.client_accounts();
+            let actual = parallel.client_accounts();
+            assert_eq!(actual.len(), expected.len());
+            for (client_id, account) in expected {
+                let parallel_account = actual.get(client_id).unwrap();
+                assert_eq!(
+                    parallel_account.available_balance,
+                    account.available_balance
+                );
+                assert_eq!(parallel_account.held_balance, account.held_balance);
+                assert_eq!(parallel_account.locked, account.locked);
+            }
+        }
+    }
+
+    #[test]
+    fn test_process_csv_parallel_splits_at_record_boundaries() {
+        let inputs = [
+            "type,client,tx,amount\rdeposit,1,1,1.0\rdeposit,2,2,2.0\rdeposit,3,3,3.0\rdeposit,4,4,4.0",
+            "type,client,tx,amount\r\ndeposit,1,1,1.0\r\ndeposit,2,2,2.0\r\ndeposit,3,3,3.0\r\ndeposit,4,4,4.0\r\n",
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,\"2.0\n\"\ndeposit,3,3,3.0\ndeposit,4,4,4.0\n",
+        ];
+
+        for csv_data in inputs {
+            let mut sequential = PaymentsEngine::new();
+            process_csv_transactions(&mut sequential, create_test_csv(csv_data)).unwrap();
+            assert_eq!(sequential.client_accounts().len(), 4);
+
+            for workers in 1..=4 {
+                let mut parallel = PaymentsEngine::new();
+                process_csv_transactions_parallel(
+                    &mut parallel,
+                    create_test_csv(csv_data),
+                    NonZeroUsize::new(workers).unwrap(),
+                )
+                .unwrap();
+
+                for (client_id, account) in sequential.client_accounts() {
+                    assert_eq!(
+                        parallel.client_accounts()[client_id].available_balance,
+                        account.available_balance
+                    );
+                }
+                assert_eq!(parallel.client_accounts().len(), 4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_process_csv_parallel_keeps_file_order_across_clients() {
+        // Transaction IDs are global, so which client's deposit 1 is applied depends on file order
+        let csv_data = "type,client,tx,amount
+deposit,2,2,1.0
+deposit,1,1,1.0
+deposit,2,1,5.0
+";
+
+        let mut sequential = PaymentsEngine::new();
+        process_csv_transactions(&mut sequential, create_test_csv(csv_data)).unwrap();
+
+        for workers in 1..=3 {
+            let mut parallel = PaymentsEngine::new();
+            process_csv_transactions_parallel(
+                &mut parallel,
+                create_test_csv(csv_data),
+                NonZeroUsize::new(workers).unwrap(),
+            )
+            .unwrap();
+
+            for client in [1, 2] {
+                let client_id = ClientId::new(client);
+                assert_eq!(
+                    parallel.client_accounts()[&client_id].available_balance,
+                    sequential.client_accounts()[&client_id].available_balance
+                );
+            }
+            assert_eq!(
+                parallel.client_accounts()[&ClientId::new(1)].available_balance,
+                Decimal::ONE
+            );
+        }
+    }
+
+    #[test]
+    fn test_process_csv_parallel_preserves_client_order_across_chunks() {
+        let mut engine = PaymentsEngine::new();
+        // The withdrawal only succeeds if it is applied after both deposits
+        let csv_data = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,2,2,1.0
+deposit,1,3,1.0
+deposit,2,4,1.0
+withdrawal,1,5,2.0
+withdrawal,2,6,2.0
+";
+
+        process_csv_transactions_parallel(
+            &mut engine,
+            create_test_csv(csv_data),
+            NonZeroUsize::new(6).unwrap(),
+        )
+        .unwrap();
+
+        let accounts = engine.client_accounts();
+        assert_eq!(accounts.len(), 2);
+        for account in accounts.values() {
+            assert_eq!(account.available_balance, Decimal::ZERO);
+        }
+    }
+
+    #[test]
+    fn test_process_csv_parallel_skips_invalid_rows() {
+        let mut engine = PaymentsEngine::new();
+        let csv_data = "type,client,tx,amount\ndeposit,1,1,1.0\ninvalid,1,2,1.0\ndeposit,1,3,-1.0\ndeposit,1,4,2.0";
+
+        process_csv_transactions_parallel(
+            &mut engine,
+            create_test_csv(csv_data),
+            NonZeroUsize::new(2).unwrap(),
+        )
+        .unwrap();
+
+        let account = engine.client_accounts().get(&ClientId::new(1)).unwrap();
+        assert_eq!(account.available_balance, dec!(3));
+    }
+
+    #[test]
+    fn test_state_at_counts_input_rows() {
+        let csv_data = "type,client,tx,amount
+deposit,1,1,10.0
+deposit,one,2,1.0
+deposit,2,3,1.0
+withdrawal,1,4,3.0
+deposit,1,5,nope";
+        let mut sequential = PaymentsEngine::new();
+        process_csv_transactions(&mut sequential, create_test_csv(csv_data)).unwrap();
+        let mut parallel = PaymentsEngine::new();
+        process_csv_transactions_parallel(
+            &mut parallel,
+            create_test_csv(csv_data),
+            NonZeroUsize::new(3).unwrap(),
+        )
+        .unwrap();
+
+        for engine in [sequential, parallel] {
+            // Malformed rows take a sequence number too
+            assert_eq!(engine.sequence(), 5);
+            let available_at = |sequence| {
+                engine
+                    .state_at(sequence)
+                    .unwrap()
+                    .client_accounts()
+                    .get(&ClientId::new(1))
+                    .map(|account| account.available_balance)
+            };
+            assert_eq!(available_at(1), Some(Decimal::TEN));
+            assert_eq!(available_at(3), Some(Decimal::TEN));
+            assert_eq!(available_at(4), Some(dec!(7)));
+            assert_eq!(engine.state_at(2).unwrap().client_accounts().len(), 1);
+            assert_eq!(engine.state_at(3).unwrap().client_accounts().len(), 2);
+            assert_eq!(engine.replay().unwrap().sequence(), 5);
+        }
+    }
+
+    #[test]
+    fn test_process_csv_parallel_headers_only() {
+        let mut engine = PaymentsEngine::new();
+
+        for csv_data in ["", "type,client,tx,amount", "type,client,tx,amount\n"] {
+            process_csv_transactions_parallel(
+                &mut engine,
+                create_test_csv(csv_data),
+                NonZeroUsize::new(4).unwrap(),
+            )
+            .unwrap();
+        }
+
+        assert!(engine.client_accounts().is_empty());
+    }
+
     #[test]
     fn test_print_account_records_empty() {
         let engine = PaymentsEngine::new();
         let mut output = Vec::new();
         print_account_records(&engine, &mut output).unwrap();
 
-        let result = String::from_utf8(output).unwrap();
-        assert!(result.is_empty() || result == "client,available,held,total,locked\n");
+        assert_eq!(String::from_utf8(output).unwrap(), "");
     }
 
     #[test]
@@ -259,9 +1011,10 @@ mod tests {
         let mut output = Vec::new();
         print_account_records(&engine, &mut output).unwrap();
 
-        let result = String::from_utf8(output).unwrap();
-        assert!(result.contains("client,available,held,total,locked"));
-        assert!(result.contains("1,1.5000,0.0000,1.5000,false"));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n"
+        );
     }
 
     #[test]
@@ -280,11 +1033,12 @@ mod tests {
         let mut output = Vec::new();
         print_account_records(&engine, &mut output).unwrap();
 
-        let result = String::from_utf8(output).unwrap();
-        assert!(result.contains("client,available,held,total,locked"));
-        assert!(
-            result.contains("1.0000,0.0000,1.0000,false")
-                && result.contains("2.5000,0.0000,2.5000,false")
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked
+1,1.0000,0.0000,1.0000,false
+2,2.5000,0.0000,2.5000,false
+"
         );
     }
 
@@ -312,6 +1066,318 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_print_account_records_with_fees() {
+        let mut engine = PaymentsEngine::new().with_fee_schedule(FeeSchedule {
+            deposit: None,
+            withdrawal: Some(FeeRule::flat(dec!(0.25))),
+            chargeback: None,
+        });
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Withdrawal, 1, 2, Some(Decimal::ONE)),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+
+        let mut output = Vec::new();
+        let options = OutputOptions {
+            include_fees: true,
+            ..OutputOptions::default()
+        };
+        print_account_records_with_options(&engine, &mut output, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked,fees\n1,8.7500,0.0000,8.7500,false,0.2500\n"
+        );
+
+        let mut output = Vec::new();
+        print_account_records(&engine, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,8.7500,0.0000,8.7500,false\n"
+        );
+
+        let mut output = Vec::new();
+        print_fee_entries(&engine, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,kind,amount\n1,2,withdrawal,0.2500\n"
+        );
+    }
+
+    #[test]
+    fn test_validate_csv_transactions() {
+        let csv_data = "type,client,tx,amount
+deposit,1,1,1.0
+transfer,1,2,1.0
+withdrawal,1,3,-1.0
+dispute,1,1,";
+
+        let errors = validate_csv_transactions(create_test_csv(csv_data)).unwrap();
+        assert_eq!(errors.len(), 2);
+        let lines: Vec<u64> = errors
+            .iter()
+            .map(|e| e.position().unwrap().line())
+            .collect();
+        assert_eq!(lines, vec![3, 4]);
+    }
+
+    /// Inputs that fail to decompress: a truncated gzip file, and one that isn't gzip at all.
+    fn unreadable_gzip_inputs() -> Vec<Vec<u8>> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        writeln!(encoder, "type,client,tx,amount").unwrap();
+        for tx in 1..=100 {
+            writeln!(encoder, "deposit,1,{tx},1.0").unwrap();
+        }
+        let mut truncated = encoder.finish().unwrap();
+        truncated.truncate(truncated.len() / 2);
+
+        vec![truncated, b"type,client,tx,amount\n".to_vec()]
+    }
+
+    fn gzip_input(data: Vec<u8>) -> Box<dyn io::Read + Send> {
+        decompress(Path::new("input.csv.gz"), Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn test_unreadable_input_fails() {
+        for data in unreadable_gzip_inputs() {
+            let mut engine = PaymentsEngine::new();
+            assert!(process_csv_transactions(&mut engine, gzip_input(data.clone())).is_err());
+
+            let mut stats = TransactionStats::default();
+            let result = collect_csv_stats(&mut engine, gzip_input(data.clone()), &mut stats);
+            assert!(result.is_err());
+
+            assert!(validate_csv_transactions(gzip_input(data)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_collect_csv_stats() {
+        let csv_data = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,2,2,2.5
+withdrawal,1,3,5.0
+dispute,2,9,
+invalid,1,4,1.0";
+
+        let mut engine = PaymentsEngine::new();
+        let mut stats = TransactionStats::default();
+        collect_csv_stats(&mut engine, create_test_csv(csv_data), &mut stats).unwrap();
+
+        assert_eq!(stats.rows, 5);
+        assert_eq!(stats.malformed_rows, 1);
+        assert_eq!(stats.clients.len(), 2);
+        assert_eq!(stats.rows_by_type[&Deposit], 2);
+        assert_eq!(stats.amount_totals[&Deposit], dec!(3.5));
+        assert_eq!(stats.rejections["InsufficientFunds"], 1);
+        assert_eq!(stats.rejections["TransactionNotFound"], 1);
+        assert_eq!(
+            engine.client_accounts()[&ClientId::new(1)].available_balance,
+            Decimal::ONE
+        );
+    }
+
+    #[test]
+    fn test_collect_csv_stats_totals_rounded_applied_amounts() {
+        let csv_data = "type,client,tx,amount
+deposit,1,1,1.005
+deposit,1,1,1.005";
+
+        let precision = Precision {
+            decimal_places: 2,
+            ..Precision::default()
+        };
+        let mut engine = PaymentsEngine::new().with_precision(precision);
+        let mut stats = TransactionStats::default();
+        collect_csv_stats(&mut engine, create_test_csv(csv_data), &mut stats).unwrap();
+
+        // The retry isn't counted again, and the total is the rounded amount that was applied
+        assert_eq!(stats.rows_by_type[&Deposit], 2);
+        assert_eq!(stats.amount_totals[&Deposit], dec!(1.00));
+        assert!(
+            stats
+                .display(engine.precision())
+                .to_string()
+                .contains("\n  deposit: 1.00\n")
+        );
+    }
+
+    #[test]
+    fn test_diff_account_records() {
+        let left = "client,available,held,total,locked
+1,1.0000,0.0000,1.0000,false
+2,2.0000,0.0000,2.0000,false
+10,5.0000,0.0000,5.0000,false";
+        let right = "client,available,held,total,locked
+10,5.0000,0.0000,5.0000,false
+1,0.5000,0.0000,0.5000,true
+3,3.0000,0.0000,3.0000,false";
+
+        let diffs = diff_account_records(create_test_csv(left), create_test_csv(right)).unwrap();
+        let lines: Vec<String> = diffs.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "~ client 1: available 1.0000 -> 0.5000",
+                "~ client 1: locked false -> true",
+                "~ client 1: total 1.0000 -> 0.5000",
+                "- 2,2.0000,0.0000,2.0000,false",
+                "+ 3,3.0000,0.0000,3.0000,false",
+            ]
+        );
+
+        let same = diff_account_records(create_test_csv(left), create_test_csv(left)).unwrap();
+        assert!(same.is_empty());
+    }
+
+    #[test]
+    fn test_diff_account_records_duplicate_client() {
+        let left = "client,available,held,total,locked
+1,1.0000,0.0000,1.0000,false
+2,2.0000,0.0000,2.0000,false
+1,9.0000,0.0000,9.0000,false";
+        let right = "client,available,held,total,locked
+2,2.0000,0.0000,2.0000,false
+1,1.0000,0.0000,1.0000,false
+2,2.0000,0.0000,2.0000,false";
+
+        let diffs = diff_account_records(create_test_csv(left), create_test_csv(right)).unwrap();
+        let lines: Vec<String> = diffs.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "! client 1 duplicated in left: 1,9.0000,0.0000,9.0000,false",
+                "! client 2 duplicated in right: 2,2.0000,0.0000,2.0000,false",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_print_interest_entries() {
+        let mut engine = PaymentsEngine::new().with_interest(InterestConfig {
+            default_rate: dec!(0.0365),
+            ..InterestConfig::default()
+        });
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(100)));
+        engine.process_transaction(deposit).unwrap();
+        engine.accrue_interest(InterestPeriod {
+            start: Date::new(2024, 1, 1).unwrap(),
+            end: Date::new(2024, 1, 11).unwrap(),
+        });
+
+        let mut output = Vec::new();
+        print_interest_entries(&engine, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,tx,start,end,amount\n1,4294967295,2024-01-01,2024-01-11,0.1000\n"
+        );
+    }
+
+    #[test]
+    fn test_statements_use_engine_precision() {
+        let mut engine = PaymentsEngine::new()
+            .with_precision(Precision {
+                decimal_places: 2,
+                rounding: Rounding::HalfUp,
+                strict: false,
+            })
+            .with_fee_schedule(FeeSchedule {
+                withdrawal: Some(FeeRule {
+                    percentage: dec!(0.015),
+                    ..FeeRule::default()
+                }),
+                ..FeeSchedule::default()
+            })
+            .with_interest(InterestConfig {
+                default_rate: dec!(0.0365),
+                ..InterestConfig::default()
+            });
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(dec!(100))),
+            create_transaction(Withdrawal, 1, 2, Some(Decimal::ONE)),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+        // 98.98 * 0.0365 * 10 / 365 = 0.09898
+        engine.accrue_interest(InterestPeriod {
+            start: Date::new(2024, 1, 1).unwrap(),
+            end: Date::new(2024, 1, 11).unwrap(),
+        });
+
+        let mut fees = Vec::new();
+        print_fee_entries(&engine, &mut fees).unwrap();
+        assert_eq!(
+            String::from_utf8(fees).unwrap(),
+            "client,tx,kind,amount\n1,2,withdrawal,0.02\n"
+        );
+        let mut interest = Vec::new();
+        print_interest_entries(&engine, &mut interest).unwrap();
+        assert_eq!(
+            String::from_utf8(interest).unwrap(),
+            "client,tx,start,end,amount\n1,4294967295,2024-01-01,2024-01-11,0.10\n"
+        );
+    }
+
+    #[test]
+    fn test_print_collections() {
+        let mut engine = PaymentsEngine::new()
+            .with_negative_balance_policy(NegativeBalancePolicy::AllowNegative);
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Withdrawal, 1, 2, Some(dec!(7.5))),
+            create_transaction(Dispute, 1, 1, None),
+            create_transaction(Chargeback, 1, 1, None),
+            create_transaction(Deposit, 2, 3, Some(Decimal::ONE)),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+
+        let mut output = Vec::new();
+        print_collections(&engine, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,owed,available,held\n1,7.5000,-7.5000,0.0000\n"
+        );
+    }
+
+    #[test]
+    fn test_print_account_records_with_overdraft() {
+        let mut engine = PaymentsEngine::new().with_limits(LimitConfig {
+            default: Limits {
+                overdraft: Some(Decimal::TEN),
+                ..Limits::default()
+            },
+            per_client: HashMap::new(),
+        });
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::ONE)),
+            create_transaction(Withdrawal, 1, 2, Some(dec!(3.5))),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+
+        let mut output = Vec::new();
+        let options = OutputOptions {
+            include_overdraft: true,
+            ..OutputOptions::default()
+        };
+        print_account_records_with_options(&engine, &mut output, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked,overdraft\n1,-2.5000,0.0000,-2.5000,false,2.5000\n"
+        );
+    }
+
     #[test]
     fn test_end_to_end_processing() {
         let mut engine = PaymentsEngine::new();
@@ -324,7 +1390,7 @@ dispute,1,1,
 resolve,1,1,";
 
         let input = create_test_csv(csv_data);
-        process_csv_transactions(&mut engine, input);
+        process_csv_transactions(&mut engine, input).unwrap();
 
         let accounts = engine.client_accounts();
         assert_eq!(accounts.len(), 2);
@@ -342,11 +1408,169 @@ resolve,1,1,";
         let mut output = Vec::new();
         print_account_records(&engine, &mut output).unwrap();
 
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked
+1,1.5000,0.0000,1.5000,false
+2,2.0000,0.0000,2.0000,false
+"
+        );
+    }
+
+    #[test]
+    fn test_print_account_records_order() {
+        let mut engine = PaymentsEngine::new();
+
+        let transactions = vec![
+            create_transaction(Deposit, 3, 1, Some(Decimal::ONE)),
+            create_transaction(Deposit, 1, 2, Some(Decimal::ONE)),
+            create_transaction(Deposit, 10, 3, Some(Decimal::TEN)),
+            create_transaction(Deposit, 2, 4, Some(dec!(2.5))),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+
+        let mut output = Vec::new();
+        print_account_records(&engine, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked
+1,1.0000,0.0000,1.0000,false
+2,2.5000,0.0000,2.5000,false
+3,1.0000,0.0000,1.0000,false
+10,10.0000,0.0000,10.0000,false
+"
+        );
+
+        let mut output = Vec::new();
+        let options = OutputOptions {
+            order: OutputOrder::Total,
+            ..OutputOptions::default()
+        };
+        print_account_records_with_options(&engine, &mut output, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked
+10,10.0000,0.0000,10.0000,false
+2,2.5000,0.0000,2.5000,false
+1,1.0000,0.0000,1.0000,false
+3,1.0000,0.0000,1.0000,false
+"
+        );
+
+        let mut output = Vec::new();
+        let options = OutputOptions {
+            order: OutputOrder::Unsorted,
+            ..OutputOptions::default()
+        };
+        print_account_records_with_options(&engine, &mut output, &options).unwrap();
         let result = String::from_utf8(output).unwrap();
-        assert!(result.contains("client,available,held,total,locked"));
-        assert!(
-            result.contains("1.5000,0.0000,1.5000,false")
-                && result.contains("2.0000,0.0000,2.0000,false")
+        let mut lines: Vec<&str> = result.lines().skip(1).collect();
+        lines.sort_unstable();
+        assert_eq!(
+            lines,
+            vec![
+                "1,1.0000,0.0000,1.0000,false",
+                "10,10.0000,0.0000,10.0000,false",
+                "2,2.5000,0.0000,2.5000,false",
+                "3,1.0000,0.0000,1.0000,false",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_print_account_records_with_precision() {
+        let mut engine = PaymentsEngine::new().with_precision(Precision {
+            decimal_places: 2,
+            rounding: Rounding::HalfUp,
+            strict: false,
+        });
+        let csv_data = "type,client,tx,amount
+deposit,1,1,1.125
+deposit,2,2,3";
+        process_csv_transactions(&mut engine, create_test_csv(csv_data)).unwrap();
+
+        let mut output = Vec::new();
+        print_account_records(&engine, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked
+1,1.13,0.00,1.13,false
+2,3.00,0.00,3.00,false
+"
+        );
+    }
+
+    #[test]
+    fn test_process_csv_transactions_strict() {
+        let mut engine = PaymentsEngine::new();
+        let csv_data = "type,client,tx,amount
+deposit,1,1,1.0
+withdrawal,1,2,5.0
+dispute,1,1";
+        process_csv_transactions_strict(&mut engine, create_test_csv(csv_data)).unwrap();
+
+        let account = &engine.client_accounts()[&ClientId::new(1)];
+        assert_eq!(account.held_balance, Decimal::ONE);
+    }
+
+    #[test]
+    fn test_process_csv_transactions_strict_unknown_type() {
+        let mut engine = PaymentsEngine::new();
+        let csv_data = "type,client,tx,amount
+deposit,1,1,1.0
+transfer,1,2,1.0
+deposit,1,3,1.0";
+
+        let error =
+            process_csv_transactions_strict(&mut engine, create_test_csv(csv_data)).unwrap_err();
+        assert_eq!(error.line, 3);
+        assert_eq!(error.column, Some(1));
+        assert_eq!(
+            error.kind,
+            InputErrorKind::UnknownTransactionType("transfer".to_string())
+        );
+        assert_eq!(
+            error.to_string(),
+            "line 3, column 1: unknown transaction type \"transfer\""
+        );
+        // The deposit before the malformed row isn't applied either
+        assert!(engine.client_accounts().is_empty());
+        assert_eq!(engine.sequence(), 0);
+    }
+
+    #[test]
+    fn test_process_csv_transactions_strict_malformed_field() {
+        let mut engine = PaymentsEngine::new();
+        let csv_data = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,one,2,1.0";
+
+        let error =
+            process_csv_transactions_strict(&mut engine, create_test_csv(csv_data)).unwrap_err();
+        assert_eq!(error.line, 3);
+        assert_eq!(error.column, Some(2));
+        assert!(matches!(error.kind, InputErrorKind::Malformed(_)));
+        assert!(engine.client_accounts().is_empty());
+    }
+
+    #[test]
+    fn test_process_csv_transactions_strict_missing_amount() {
+        let mut engine = PaymentsEngine::new();
+        let csv_data = "type,client,tx,amount
+deposit,1,1,1.0
+withdrawal,1,2,";
+
+        let error =
+            process_csv_transactions_strict(&mut engine, create_test_csv(csv_data)).unwrap_err();
+        assert_eq!(error.line, 3);
+        assert_eq!(error.column, Some(4));
+        assert_eq!(error.kind, InputErrorKind::MissingAmount(Withdrawal));
+        assert_eq!(
+            error.to_string(),
+            "line 3, column 4: withdrawal without an amount"
         );
+        assert!(engine.client_accounts().is_empty());
     }
 }
@@ -1,11 +1,12 @@
 use crate::domain::TransactionStatus::Pending;
-use crate::engine::ClientAccount;
+use crate::engine::{ClientAccount, ProcessingError};
+use crate::precision::Precision;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
 #[serde(transparent)]
 pub struct ClientId(u16);
 
@@ -25,7 +26,7 @@ impl Display for ClientId {
     }
 }
 
-#[derive(Deserialize, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq, Hash)]
 #[serde(transparent)]
 pub struct TransactionId(u32);
 
@@ -39,7 +40,13 @@ impl TransactionId {
     }
 }
 
-#[derive(Deserialize, Debug, PartialEq, Clone)]
+impl Display for TransactionId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -47,11 +54,55 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Moves funds from available to held, until they are captured, voided or the hold expires
+    Authorize,
+    /// Settles part or all of an authorized amount, releasing the rest
+    Capture,
+    /// Releases an authorized amount
+    Void,
+    /// Returns part or all of a deposit, referencing it by its transaction ID
+    Refund,
+    /// Interest credited by the engine itself, see
+    /// [`crate::engine::PaymentsEngine::accrue_interest`]. It can't be submitted.
+    #[serde(skip_deserializing)]
+    Interest,
 }
 
 impl TransactionType {
     pub fn is_standard_transaction(&self) -> bool {
-        matches!(self, TransactionType::Deposit | TransactionType::Withdrawal)
+        matches!(
+            self,
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Authorize
+        )
+    }
+
+    /// Whether rows of this type must have an amount. Captures without one settle the whole hold.
+    pub fn requires_amount(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Deposit
+                | TransactionType::Withdrawal
+                | TransactionType::Authorize
+                | TransactionType::Refund
+        )
+    }
+}
+
+impl Display for TransactionType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Authorize => "authorize",
+            TransactionType::Capture => "capture",
+            TransactionType::Void => "void",
+            TransactionType::Refund => "refund",
+            TransactionType::Interest => "interest",
+        };
+        write!(f, "{name}")
     }
 }
 
@@ -108,7 +159,8 @@ impl Serialize for Amount {
     where
         S: Serializer,
     {
-        serializer.serialize_str(&format!("{:.4}", self.value()))
+        // The engine rounds amounts to its precision, so they are written as they are
+        serializer.collect_str(&self.value())
     }
 }
 
@@ -127,7 +179,19 @@ pub struct Transaction {
     pub client: ClientId,
     pub tx: TransactionId,
     pub amount: Option<Amount>,
+    /// Amount as submitted, before it was rounded to the precision of the engine. Retries must
+    /// submit exactly the same amount.
+    pub submitted_amount: Option<Amount>,
     pub tx_status: TransactionStatus,
+    /// Total refunded so far against this transaction
+    pub refunded: Decimal,
+}
+
+impl Transaction {
+    /// Part of the amount that hasn't been refunded yet, and can therefore be refunded or disputed.
+    pub fn unrefunded_amount(&self) -> Option<Decimal> {
+        self.amount.map(|amount| amount.value() - self.refunded)
+    }
 }
 
 impl From<TransactionRow> for Transaction {
@@ -137,50 +201,143 @@ impl From<TransactionRow> for Transaction {
             client: value.client,
             tx: value.tx,
             amount: value.amount,
+            submitted_amount: value.amount,
             tx_status: Pending,
+            refunded: Decimal::ZERO,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TransactionStatus {
     Pending,
     Settled,
     Disputed,
     Resolved,
     ChargedBack,
+    /// Deposit refunded in full
+    Refunded,
+    /// Authorized funds that are still held
+    Authorized,
+    Captured,
+    Voided,
+    /// Authorized funds released because the hold expired before being captured
+    Expired,
+    /// Deposit, withdrawal or authorization that was not applied, with the reason why
+    Rejected(Box<ProcessingError>),
+}
+
+impl Display for TransactionStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TransactionStatus::Pending => "pending",
+            TransactionStatus::Settled => "settled",
+            TransactionStatus::Disputed => "disputed",
+            TransactionStatus::Resolved => "resolved",
+            TransactionStatus::ChargedBack => "charged back",
+            TransactionStatus::Refunded => "refunded",
+            TransactionStatus::Authorized => "authorized",
+            TransactionStatus::Captured => "captured",
+            TransactionStatus::Voided => "voided",
+            TransactionStatus::Expired => "expired",
+            TransactionStatus::Rejected(_) => "rejected",
+        };
+        write!(f, "{name}")
+    }
 }
 
+/// Balances of a client as written to the output, rounded to the precision of the engine.
 #[derive(Debug, Serialize)]
 pub struct ClientAccountOutput {
     client: ClientId,
-    #[serde(serialize_with = "serialize_decimal_with_precision_4")]
+    #[serde(serialize_with = "serialize_decimal")]
     available: Decimal,
-    #[serde(serialize_with = "serialize_decimal_with_precision_4")]
+    #[serde(serialize_with = "serialize_decimal")]
     held: Decimal,
-    #[serde(serialize_with = "serialize_decimal_with_precision_4")]
+    #[serde(serialize_with = "serialize_decimal")]
     total: Decimal,
     locked: bool,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_optional_decimal"
+    )]
+    fees: Option<Decimal>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_optional_decimal"
+    )]
+    overdraft: Option<Decimal>,
+    #[serde(skip)]
+    precision: Precision,
 }
 
-impl From<(&ClientId, &ClientAccount)> for ClientAccountOutput {
-    fn from((client_id, client_account): (&ClientId, &ClientAccount)) -> Self {
+impl ClientAccountOutput {
+    pub fn new(client_id: ClientId, client_account: &ClientAccount, precision: Precision) -> Self {
         Self {
-            client: *client_id,
-            available: client_account.available_balance,
-            held: client_account.held_balance,
-            total: client_account.total(),
+            client: client_id,
+            available: precision.round_padded(client_account.available_balance),
+            held: precision.round_padded(client_account.held_balance),
+            total: precision.round_padded(client_account.total()),
             locked: client_account.locked,
+            fees: None,
+            overdraft: None,
+            precision,
         }
     }
+
+    /// Adds the `fees` column, with the total fees charged to the account.
+    pub fn with_fees(mut self, fees: Decimal) -> Self {
+        self.fees = Some(self.precision.round_padded(fees));
+        self
+    }
+
+    /// Adds the `overdraft` column, with how much of the client's credit line is in use.
+    pub fn with_overdraft(mut self, overdraft: Decimal) -> Self {
+        self.overdraft = Some(self.precision.round_padded(overdraft));
+        self
+    }
 }
 
-fn serialize_decimal_with_precision_4<S>(
-    decimal: &Decimal,
+/// A locked account that owes money, as reported in the collections output.
+#[derive(Debug, Serialize)]
+pub struct CollectionOutput {
+    client: ClientId,
+    #[serde(serialize_with = "serialize_decimal")]
+    owed: Decimal,
+    #[serde(serialize_with = "serialize_decimal")]
+    available: Decimal,
+    #[serde(serialize_with = "serialize_decimal")]
+    held: Decimal,
+}
+
+impl CollectionOutput {
+    pub fn new(client_id: ClientId, client_account: &ClientAccount, precision: Precision) -> Self {
+        Self {
+            client: client_id,
+            owed: precision.round_padded(client_account.receivable()),
+            available: precision.round_padded(client_account.available_balance),
+            held: precision.round_padded(client_account.held_balance),
+        }
+    }
+}
+
+/// Writes the decimal with its own scale, for values already rounded with [`Precision::round_padded`].
+pub(crate) fn serialize_decimal<S>(decimal: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_str(decimal)
+}
+
+fn serialize_optional_decimal<S>(
+    decimal: &Option<Decimal>,
     serializer: S,
 ) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    serializer.serialize_str(&format!("{decimal:.4}"))
+    match decimal {
+        Some(decimal) => serialize_decimal(decimal, serializer),
+        None => serializer.serialize_none(),
+    }
 }
@@ -1,18 +1,43 @@
-use crate::domain::TransactionStatus::{ChargedBack, Disputed, Resolved, Settled};
-use crate::domain::{ClientId, Transaction, TransactionId, TransactionType};
+use crate::clock::{Clock, SystemClock, Timestamp};
+use crate::domain::TransactionStatus::{
+    Authorized, Captured, ChargedBack, Disputed, Expired, Refunded, Rejected, Resolved, Settled,
+    Voided,
+};
+use crate::domain::{
+    Amount, ClientId, Transaction, TransactionId, TransactionStatus, TransactionType,
+};
 use crate::engine::ProcessingError::{
-    BalanceOverflow, InsufficientFunds, InvalidDispute, InvalidTransactionStatus, MissingAmount,
+    AccountLocked, BalanceOverflow, ClientMismatch, IdempotencyConflict, InsufficientFunds,
+    InvalidDispute, InvalidRefund, InvalidTransactionStatus, LimitExceeded, MissingAmount,
+    PrecisionExceeded, RefundTooLarge, RiskRejected, SystemTransaction, TransactionIdConflict,
     TransactionNotFound,
 };
+use crate::events::{Event, EventRecord, SequenceNumber};
+use crate::fees::{FeeEntry, FeeKind, FeeSchedule};
+use crate::interest::{InterestConfig, InterestEntry, InterestPeriod};
+use crate::ledger::{EntryKind, Journal, JournalEntry, LedgerAccount, Posting};
+use crate::limits::{ClientActivity, LimitConfig, LimitKind};
+use crate::precision::Precision;
+use crate::risk::{AuditEntry, RiskContext, RiskOutcome, RiskProfile, RiskRule};
+use TransactionType::{
+    Authorize, Capture, Chargeback, Deposit, Dispute, Interest, Refund, Resolve, Void, Withdrawal,
+};
 use rust_decimal::Decimal;
-use std::collections::HashMap;
-use TransactionType::{Chargeback, Deposit, Dispute, Resolve, Withdrawal};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::hash::Hash;
 
-#[derive(Debug, Clone, Default)]
+/// Balances of a client, as projected from the entries posted to its accounts in the [`Journal`].
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct ClientAccount {
     pub available_balance: Decimal,
     pub held_balance: Decimal,
     pub locked: bool,
+    /// Set by the [`ClientMismatchPolicy`] or a [`RiskRule`] for accounts that need to be reviewed
+    pub flagged: bool,
+    /// Total fees charged to the account, already deducted from the available balance
+    pub fees: Decimal,
 }
 
 impl ClientAccount {
@@ -21,22 +46,497 @@ impl ClientAccount {
             .checked_add(self.held_balance)
             .unwrap_or(Decimal::MAX)
     }
+
+    /// How much of the client's credit line is in use, i.e. how far below zero the available balance is.
+    pub fn overdraft_used(&self) -> Decimal {
+        (-self.available_balance).max(Decimal::ZERO)
+    }
+
+    /// How much the client owes, i.e. how far below zero the total balance is.
+    pub fn receivable(&self) -> Decimal {
+        (-self.total()).max(Decimal::ZERO)
+    }
 }
 
-#[derive(Debug, PartialEq)]
+/// Reason why a transaction was rejected, with enough context to report it precisely.
+#[derive(Debug, Clone, PartialEq)]
 pub enum ProcessingError {
-    MissingAmount,
-    InsufficientFunds,
-    BalanceOverflow,
-    AccountLocked,
-    TransactionNotFound,
-    InvalidTransactionStatus,
-    InvalidDispute,
+    MissingAmount {
+        client: ClientId,
+        tx: TransactionId,
+    },
+    InsufficientFunds {
+        client: ClientId,
+        tx: TransactionId,
+        requested: Decimal,
+        available: Decimal,
+    },
+    BalanceOverflow {
+        client: ClientId,
+        tx: TransactionId,
+    },
+    AccountLocked {
+        client: ClientId,
+    },
+    TransactionNotFound {
+        client: ClientId,
+        tx: TransactionId,
+    },
+    /// A dispute, resolve or chargeback referenced a transaction that belongs to another client
+    ClientMismatch {
+        client: ClientId,
+        tx: TransactionId,
+        owner: ClientId,
+    },
+    InvalidTransactionStatus {
+        client: ClientId,
+        tx: TransactionId,
+        expected: TransactionStatus,
+        actual: TransactionStatus,
+    },
+    InvalidDispute {
+        client: ClientId,
+        tx: TransactionId,
+        tx_type: TransactionType,
+        tx_status: TransactionStatus,
+    },
+    InvalidRefund {
+        client: ClientId,
+        tx: TransactionId,
+        tx_type: TransactionType,
+        tx_status: TransactionStatus,
+    },
+    /// A refund asked for more than what is left of the original deposit after earlier refunds
+    RefundTooLarge {
+        client: ClientId,
+        tx: TransactionId,
+        requested: Decimal,
+        refundable: Decimal,
+    },
+    LimitExceeded {
+        client: ClientId,
+        tx: TransactionId,
+        limit: LimitKind,
+    },
+    RiskRejected {
+        client: ClientId,
+        tx: TransactionId,
+        rule: String,
+        reason: String,
+    },
+    /// The transaction ID belongs to another transaction, of another client or of another type
+    TransactionIdConflict {
+        client: ClientId,
+        tx: TransactionId,
+    },
+    /// The same transaction, by type, client and ID, was already submitted with another amount
+    IdempotencyConflict {
+        client: ClientId,
+        tx: TransactionId,
+    },
+    /// Transactions of this type are only generated by the engine, e.g. interest postings
+    SystemTransaction {
+        client: ClientId,
+        tx: TransactionId,
+        tx_type: TransactionType,
+    },
+    /// The amount has more decimal places than the [`Precision`] allows, and either the precision
+    /// is strict or the amount rounds to zero
+    PrecisionExceeded {
+        client: ClientId,
+        tx: TransactionId,
+        amount: Decimal,
+        decimal_places: u32,
+    },
+}
+
+impl ProcessingError {
+    /// Name of the variant, e.g. to count rejections by reason.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MissingAmount { .. } => "MissingAmount",
+            InsufficientFunds { .. } => "InsufficientFunds",
+            BalanceOverflow { .. } => "BalanceOverflow",
+            AccountLocked { .. } => "AccountLocked",
+            TransactionNotFound { .. } => "TransactionNotFound",
+            ClientMismatch { .. } => "ClientMismatch",
+            InvalidTransactionStatus { .. } => "InvalidTransactionStatus",
+            InvalidDispute { .. } => "InvalidDispute",
+            InvalidRefund { .. } => "InvalidRefund",
+            RefundTooLarge { .. } => "RefundTooLarge",
+            LimitExceeded { .. } => "LimitExceeded",
+            RiskRejected { .. } => "RiskRejected",
+            TransactionIdConflict { .. } => "TransactionIdConflict",
+            IdempotencyConflict { .. } => "IdempotencyConflict",
+            SystemTransaction { .. } => "SystemTransaction",
+            PrecisionExceeded { .. } => "PrecisionExceeded",
+        }
+    }
+
+    pub fn client(&self) -> ClientId {
+        match self {
+            MissingAmount { client, .. }
+            | InsufficientFunds { client, .. }
+            | BalanceOverflow { client, .. }
+            | AccountLocked { client }
+            | TransactionNotFound { client, .. }
+            | ClientMismatch { client, .. }
+            | InvalidTransactionStatus { client, .. }
+            | InvalidDispute { client, .. }
+            | InvalidRefund { client, .. }
+            | RefundTooLarge { client, .. }
+            | LimitExceeded { client, .. }
+            | RiskRejected { client, .. }
+            | TransactionIdConflict { client, .. }
+            | IdempotencyConflict { client, .. }
+            | SystemTransaction { client, .. }
+            | PrecisionExceeded { client, .. } => *client,
+        }
+    }
+}
+
+impl Display for ProcessingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MissingAmount { client, tx } => {
+                write!(f, "Transaction {tx} of client {client} has no amount")
+            }
+            InsufficientFunds {
+                client,
+                tx,
+                requested,
+                available,
+            } => write!(
+                f,
+                "Transaction {tx} of client {client} requires {requested} but only {available} is available"
+            ),
+            BalanceOverflow { client, tx } => write!(
+                f,
+                "Transaction {tx} would overflow the balance of client {client}"
+            ),
+            AccountLocked { client } => write!(f, "Account of client {client} is locked"),
+            TransactionNotFound { client, tx } => {
+                write!(f, "Transaction {tx} not found for client {client}")
+            }
+            ClientMismatch { client, tx, owner } => write!(
+                f,
+                "Transaction {tx} belongs to client {owner}, not to client {client}"
+            ),
+            InvalidTransactionStatus {
+                client,
+                tx,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Transaction {tx} of client {client} is {actual}, expected it to be {expected}"
+            ),
+            InvalidDispute {
+                client,
+                tx,
+                tx_type,
+                tx_status,
+            } => write!(
+                f,
+                "Transaction {tx} of client {client} cannot be disputed, as it is a {tx_status} {tx_type}"
+            ),
+            InvalidRefund {
+                client,
+                tx,
+                tx_type,
+                tx_status,
+            } => write!(
+                f,
+                "Transaction {tx} of client {client} cannot be refunded, as it is a {tx_status} {tx_type}"
+            ),
+            RefundTooLarge {
+                client,
+                tx,
+                requested,
+                refundable,
+            } => write!(
+                f,
+                "Refund of {requested} for transaction {tx} of client {client} exceeds the {refundable} left to refund"
+            ),
+            LimitExceeded { client, tx, limit } => {
+                write!(f, "Transaction {tx} of client {client} exceeds the {limit}")
+            }
+            RiskRejected {
+                client,
+                tx,
+                rule,
+                reason,
+            } => write!(
+                f,
+                "Transaction {tx} of client {client} was rejected by the {rule} rule: {reason}"
+            ),
+            TransactionIdConflict { client, tx } => write!(
+                f,
+                "Transaction {tx} of client {client} reuses the ID of a different transaction"
+            ),
+            IdempotencyConflict { client, tx } => write!(
+                f,
+                "Transaction {tx} of client {client} was already submitted with a different payload"
+            ),
+            SystemTransaction {
+                client,
+                tx,
+                tx_type,
+            } => write!(
+                f,
+                "Transaction {tx} of client {client} is an {tx_type} posting, which only the engine can make"
+            ),
+            PrecisionExceeded {
+                client,
+                tx,
+                amount,
+                decimal_places,
+            } => write!(
+                f,
+                "Amount {amount} of transaction {tx} of client {client} has more than {decimal_places} decimal places"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProcessingError {}
+
+/// [`PaymentsEngine::commit`] or [`PaymentsEngine::rollback`] was called without an open batch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoOpenBatch;
+
+impl Display for NoOpenBatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No batch is open")
+    }
+}
+
+impl std::error::Error for NoOpenBatch {}
+
+/// A transaction of a batch given to [`PaymentsEngine::process_batch`] was rejected, so none of
+/// the batch was applied.
+#[derive(Debug, Clone)]
+pub struct BatchRejected {
+    /// Position of the rejected transaction in the batch, counting from 0
+    pub index: usize,
+    pub error: ProcessingError,
+}
+
+impl Display for BatchRejected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Batch rolled back, transaction {} was rejected: {}",
+            self.index, self.error
+        )
+    }
+}
+
+impl std::error::Error for BatchRejected {}
+
+/// An event couldn't be applied again while rebuilding the engine with
+/// [`PaymentsEngine::state_at`], which means the event log doesn't match the engine's configuration.
+#[derive(Debug, Clone)]
+pub struct ReplayFailed {
+    /// Sequence number of the event that failed
+    pub sequence: SequenceNumber,
+    pub error: ProcessingError,
+}
+
+impl Display for ReplayFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Replay failed at sequence {}: {}",
+            self.sequence, self.error
+        )
+    }
+}
+
+impl std::error::Error for ReplayFailed {}
+
+/// What to do with an account that keeps referencing other clients' transactions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClientMismatchAction {
+    Flag,
+    Lock,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientMismatchPolicy {
+    /// Number of [`ProcessingError::ClientMismatch`] rejections after which the action is applied
+    pub threshold: u32,
+    pub action: ClientMismatchAction,
+}
+
+/// What to do with a dispute when the client no longer has the disputed funds available, e.g.
+/// because they were withdrawn after the deposit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum NegativeBalancePolicy {
+    /// Refuse the dispute with [`ProcessingError::InsufficientFunds`]
+    #[default]
+    Refuse,
+    /// Hold the disputed funds anyway, driving the available balance negative. If the dispute is
+    /// charged back, the client is left owing the difference as a receivable.
+    AllowNegative,
+}
+
+/// How deposits, withdrawals and authorizations are identified, to tell retries from new
+/// transactions.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum IdNamespace {
+    /// IDs are unique across all clients, and disputes of another client's transaction are
+    /// reported as [`ProcessingError::ClientMismatch`]
+    #[default]
+    Global,
+    /// Each client has its own IDs, so different clients may use the same ID. Disputes only ever
+    /// reference the client's own transactions.
+    PerClient,
+    /// IDs are unique across all clients like with [`IdNamespace::Global`], and a transaction with
+    /// the same type, client and amount as an earlier one is also a retry of it, whatever its ID.
+    /// Two transactions with the same content can therefore never both be applied.
+    ContentHash,
+}
+
+/// Content of a deposit, withdrawal or authorization, identifying it with
+/// [`IdNamespace::ContentHash`]. Amounts are the ones submitted, before rounding.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Payload {
+    tx_type: TransactionType,
+    client: ClientId,
+    amount: Option<Decimal>,
+}
+
+impl Payload {
+    fn of(transaction: &Transaction) -> Self {
+        Self {
+            tx_type: transaction.tx_type.clone(),
+            client: transaction.client,
+            amount: transaction.submitted_amount.map(|amount| amount.value()),
+        }
+    }
+}
+
+/// Key of a transaction in the history.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum TransactionKey {
+    /// Transaction of the input. The client is only part of the key with
+    /// [`IdNamespace::PerClient`].
+    Input {
+        client: Option<ClientId>,
+        tx: TransactionId,
+    },
+    /// Transaction generated by the engine, e.g. an interest posting. Its IDs are separate from the
+    /// ones of the input, so the two never collide.
+    System(TransactionId),
+}
+
+/// State of the engine when a batch was begun, restored if the batch is rolled back. The ledgers,
+/// the journal, the audit trail and the events only grow, so only their lengths are kept, and the
+/// clients and transactions are only saved once the batch changes them.
+struct Checkpoint {
+    undo: UndoLog,
+    next_system_tx: u32,
+    sequence: SequenceNumber,
+    fee_ledger_len: usize,
+    journal_len: usize,
+    audit_trail_len: usize,
+    interest_ledger_len: usize,
+    events_len: usize,
+}
+
+/// Clients and transactions changed during a batch, as they were before the batch first changed
+/// them. `None` means that they didn't exist yet.
+#[derive(Default)]
+struct UndoLog {
+    clients: HashMap<ClientId, ClientState>,
+    transactions: HashMap<TransactionKey, Option<Transaction>>,
+    /// Periods accrued during the batch
+    accrued_periods: Vec<InterestPeriod>,
+}
+
+/// Everything the engine keeps about a client, as saved in an [`UndoLog`].
+struct ClientState {
+    account: Option<ClientAccount>,
+    mismatches: Option<u32>,
+    activity: Option<ClientActivity>,
+    risk_profile: Option<RiskProfile>,
+    holds: Option<Vec<(Timestamp, TransactionId)>>,
+}
+
+impl UndoLog {
+    /// Takes over the changes of a committed inner batch. Where both batches changed the same
+    /// client or transaction, the state saved by this outer batch is the older one and is kept.
+    fn merge(&mut self, inner: UndoLog) {
+        for (client_id, state) in inner.clients {
+            self.clients.entry(client_id).or_insert(state);
+        }
+        for (key, transaction) in inner.transactions {
+            self.transactions.entry(key).or_insert(transaction);
+        }
+        self.accrued_periods.extend(inner.accrued_periods);
+    }
+}
+
+/// Puts back a value saved in an [`UndoLog`], removing the key if it didn't exist.
+fn restore<K: Eq + Hash, V>(map: &mut HashMap<K, V>, key: K, value: Option<V>) {
+    match value {
+        Some(value) => map.insert(key, value),
+        None => map.remove(&key),
+    };
 }
 
 pub struct PaymentsEngine {
     clients: HashMap<ClientId, ClientAccount>,
-    transaction_history: HashMap<TransactionId, Transaction>,
+    transaction_history: HashMap<TransactionKey, Transaction>,
+    client_mismatches: HashMap<ClientId, u32>,
+    client_mismatch_policy: Option<ClientMismatchPolicy>,
+    negative_balance_policy: NegativeBalancePolicy,
+    id_namespace: IdNamespace,
+    /// ID of the first transaction with each content, with [`IdNamespace::ContentHash`]
+    payloads: HashMap<Payload, TransactionId>,
+    precision: Precision,
+    fee_schedule: FeeSchedule,
+    fee_ledger: Vec<FeeEntry>,
+    journal: Journal,
+    limits: LimitConfig,
+    client_activity: HashMap<ClientId, ClientActivity>,
+    clock: Box<dyn Clock>,
+    risk_rules: Vec<Box<dyn RiskRule>>,
+    risk_profiles: HashMap<ClientId, RiskProfile>,
+    audit_trail: Vec<AuditEntry>,
+    /// Seconds after which authorized funds that were neither captured nor voided are released
+    hold_expiry: Option<u64>,
+    /// Open authorizations of each client, with the time they were authorized at, oldest first
+    holds: HashMap<ClientId, Vec<(Timestamp, TransactionId)>>,
+    interest: InterestConfig,
+    interest_ledger: Vec<InterestEntry>,
+    accrued_periods: HashSet<InterestPeriod>,
+    /// ID of the next transaction generated by the engine, counting down from `u32::MAX`
+    next_system_tx: u32,
+    /// Number of transactions processed so far, whether they were applied or not
+    sequence: SequenceNumber,
+    events: Vec<EventRecord>,
+    /// Checkpoints of the open batches, innermost last
+    batches: Vec<Checkpoint>,
+}
+
+/// Whether the transaction resubmits the original one. Amounts are compared as submitted, so a
+/// retry can't differ by digits that rounding or strict precision would otherwise deal with.
+fn is_same_payload(original: &Transaction, transaction: &Transaction) -> bool {
+    original.tx_type == transaction.tx_type
+        && original.client == transaction.client
+        && original.tx == transaction.tx
+        && original.submitted_amount.map(|amount| amount.value())
+            == transaction.submitted_amount.map(|amount| amount.value())
+}
+
+/// Outcome of a transaction as it was first processed, returned to its retries.
+fn original_outcome(original: &Transaction) -> Result<(), ProcessingError> {
+    match &original.tx_status {
+        Rejected(error) => Err(error.as_ref().clone()),
+        _ => Ok(()),
+    }
 }
 
 impl Default for PaymentsEngine {
@@ -50,750 +550,4020 @@ impl PaymentsEngine {
         Self {
             clients: HashMap::new(),
             transaction_history: HashMap::new(),
+            client_mismatches: HashMap::new(),
+            client_mismatch_policy: None,
+            negative_balance_policy: NegativeBalancePolicy::default(),
+            id_namespace: IdNamespace::default(),
+            payloads: HashMap::new(),
+            precision: Precision::default(),
+            fee_schedule: FeeSchedule::default(),
+            fee_ledger: Vec::new(),
+            journal: Journal::default(),
+            limits: LimitConfig::default(),
+            client_activity: HashMap::new(),
+            clock: Box::new(SystemClock),
+            risk_rules: Vec::new(),
+            risk_profiles: HashMap::new(),
+            audit_trail: Vec::new(),
+            hold_expiry: None,
+            holds: HashMap::new(),
+            interest: InterestConfig::default(),
+            interest_ledger: Vec::new(),
+            accrued_periods: HashSet::new(),
+            next_system_tx: u32::MAX,
+            sequence: 0,
+            events: Vec::new(),
+            batches: Vec::new(),
         }
     }
 
-    pub fn process_transaction(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
-        let client = self.clients.entry(transaction.client).or_default();
+    /// Adds a rule checked against every deposit and withdrawal, after the rules added before it.
+    pub fn with_risk_rule(mut self, rule: impl RiskRule + 'static) -> Self {
+        self.risk_rules.push(Box::new(rule));
+        self
+    }
 
-        if client.locked {
-            return Err(ProcessingError::AccountLocked);
+    pub fn with_interest(mut self, interest: InterestConfig) -> Self {
+        self.interest = interest;
+        self
+    }
+
+    pub fn with_id_namespace(mut self, id_namespace: IdNamespace) -> Self {
+        self.id_namespace = id_namespace;
+        self
+    }
+
+    /// Rounds input amounts to the given precision, or refuses them if it is strict.
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Releases authorized funds that haven't been captured or voided after the given number of seconds.
+    pub fn with_hold_expiry(mut self, seconds: u64) -> Self {
+        self.hold_expiry = Some(seconds);
+        self
+    }
+
+    pub fn with_limits(mut self, limits: LimitConfig) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Replaces the system clock used by time-based rules, e.g. to replay historical files.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    pub fn with_fee_schedule(mut self, fee_schedule: FeeSchedule) -> Self {
+        self.fee_schedule = fee_schedule;
+        self
+    }
+
+    pub fn with_negative_balance_policy(mut self, policy: NegativeBalancePolicy) -> Self {
+        self.negative_balance_policy = policy;
+        self
+    }
+
+    pub fn with_client_mismatch_policy(mut self, policy: ClientMismatchPolicy) -> Self {
+        self.client_mismatch_policy = Some(policy);
+        self
+    }
+
+    /// Processes a transaction. Deposits, withdrawals and authorizations are idempotent: retrying
+    /// one returns its original outcome, including the reason it was rejected, without processing
+    /// it again.
+    pub fn process_transaction(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
+        self.sequence += 1;
+        // Everything a transaction changes about clients is about its own client
+        self.save_client(transaction.client);
+        if !transaction.tx_type.is_standard_transaction() {
+            return self.process_once(transaction);
         }
 
-        if transaction.tx_type.is_standard_transaction()
-            && self.transaction_history.contains_key(&transaction.tx)
+        if let Some(original_tx) = self.transaction(transaction.client, transaction.tx) {
+            let (client, tx) = (transaction.client, transaction.tx);
+            if original_tx.client != client || original_tx.tx_type != transaction.tx_type {
+                return Err(TransactionIdConflict { client, tx });
+            }
+            if !is_same_payload(original_tx, &transaction) {
+                return Err(IdempotencyConflict { client, tx });
+            }
+            return original_outcome(original_tx);
+        }
+        if self.id_namespace == IdNamespace::ContentHash
+            && let Some(original_tx) = self
+                .payloads
+                .get(&Payload::of(&transaction))
+                .and_then(|tx| self.transaction(transaction.client, *tx))
         {
-            // Transaction was already processed, let's skip this
-            return Ok(());
+            return original_outcome(original_tx);
+        }
+
+        let result = self.process_once(transaction.clone());
+        if let Err(error) = &result {
+            self.record(Event::TransactionRejected {
+                transaction: transaction.clone(),
+                error: error.clone(),
+            });
+            self.remember_rejection(transaction, error.clone());
+        }
+
+        result
+    }
+
+    fn process_once(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
+        self.open_account(transaction.client)?;
+        let transaction = self.round_amount(transaction)?;
+
+        let now = self.clock.now();
+        self.expire_client_holds(transaction.client, now);
+        self.check_limits(&transaction, now)?;
+        if transaction.tx_type.is_standard_transaction() {
+            self.check_risk_rules(&transaction, now)?;
+        }
+
+        let event = Event::TransactionApplied {
+            transaction: transaction.clone(),
+            at: now,
+        };
+        self.apply_transaction(transaction, now)?;
+        self.record(event);
+
+        Ok(())
+    }
+
+    /// Keeps a rejected deposit, withdrawal or authorization in the history, so that retries and
+    /// references to it are answered with the reason it was rejected. Its amount is rounded like
+    /// the ones of applied transactions, and dropped if it rounds to zero.
+    fn remember_rejection(&mut self, mut transaction: Transaction, error: ProcessingError) {
+        transaction.amount = transaction
+            .amount
+            .and_then(|amount| Amount::new(self.precision.round(amount.value())).ok());
+        transaction.tx_status = Rejected(Box::new(error));
+        self.insert_transaction(transaction);
+    }
+
+    /// Adds a deposit, withdrawal or authorization to the history, and with
+    /// [`IdNamespace::ContentHash`] indexes it by its content.
+    fn insert_transaction(&mut self, transaction: Transaction) {
+        let key = self.key(transaction.client, transaction.tx);
+        self.save_transaction(key);
+        if self.id_namespace == IdNamespace::ContentHash {
+            self.payloads
+                .entry(Payload::of(&transaction))
+                .or_insert(transaction.tx);
+        }
+        self.transaction_history.insert(key, transaction);
+    }
+
+    fn key(&self, client_id: ClientId, tx: TransactionId) -> TransactionKey {
+        let client = match self.id_namespace {
+            IdNamespace::Global | IdNamespace::ContentHash => None,
+            IdNamespace::PerClient => Some(client_id),
+        };
+        TransactionKey::Input { client, tx }
+    }
+
+    /// Rounds the amount of the transaction to the configured [`Precision`].
+    fn round_amount(&self, mut transaction: Transaction) -> Result<Transaction, ProcessingError> {
+        let Some(amount) = transaction.amount else {
+            return Ok(transaction);
+        };
+
+        let exceeded = PrecisionExceeded {
+            client: transaction.client,
+            tx: transaction.tx,
+            amount: amount.value(),
+            decimal_places: self.precision.decimal_places,
+        };
+        if self.precision.strict && self.precision.is_exceeded_by(amount.value()) {
+            return Err(exceeded);
+        }
+        let rounded = Amount::new(self.precision.round(amount.value())).map_err(|_| exceeded)?;
+        transaction.amount = Some(rounded);
+
+        Ok(transaction)
+    }
+
+    /// Starts a batch: everything processed until the matching [`Self::commit`] or
+    /// [`Self::rollback`] is applied as one unit. Batches can be nested.
+    ///
+    /// The batch only saves the clients and transactions it changes, so its cost grows with the
+    /// size of the batch rather than of the engine.
+    pub fn begin_batch(&mut self) {
+        self.batches.push(Checkpoint {
+            undo: UndoLog::default(),
+            next_system_tx: self.next_system_tx,
+            sequence: self.sequence,
+            fee_ledger_len: self.fee_ledger.len(),
+            journal_len: self.journal.entries().len(),
+            audit_trail_len: self.audit_trail.len(),
+            interest_ledger_len: self.interest_ledger.len(),
+            events_len: self.events.len(),
+        });
+    }
+
+    /// Keeps the changes of the innermost batch. They are still undone if an outer batch is
+    /// rolled back.
+    pub fn commit(&mut self) -> Result<(), NoOpenBatch> {
+        let checkpoint = self.batches.pop().ok_or(NoOpenBatch)?;
+        if let Some(outer) = self.batches.last_mut() {
+            outer.undo.merge(checkpoint.undo);
+        }
+
+        Ok(())
+    }
+
+    /// Undoes every change made since the innermost batch was begun, leaving no trace of it.
+    pub fn rollback(&mut self) -> Result<(), NoOpenBatch> {
+        let checkpoint = self.batches.pop().ok_or(NoOpenBatch)?;
+
+        let undo = checkpoint.undo;
+        for (client_id, state) in undo.clients {
+            restore(&mut self.clients, client_id, state.account);
+            restore(&mut self.client_mismatches, client_id, state.mismatches);
+            restore(&mut self.client_activity, client_id, state.activity);
+            restore(&mut self.risk_profiles, client_id, state.risk_profile);
+            restore(&mut self.holds, client_id, state.holds);
+        }
+        for (key, transaction) in undo.transactions {
+            // Transactions added by the batch no longer identify their content
+            if transaction.is_none()
+                && let Some(added) = self.transaction_history.get(&key)
+            {
+                let payload = Payload::of(added);
+                if self.payloads.get(&payload) == Some(&added.tx) {
+                    self.payloads.remove(&payload);
+                }
+            }
+            restore(&mut self.transaction_history, key, transaction);
+        }
+        for period in &undo.accrued_periods {
+            self.accrued_periods.remove(period);
+        }
+        self.next_system_tx = checkpoint.next_system_tx;
+        self.sequence = checkpoint.sequence;
+        self.fee_ledger.truncate(checkpoint.fee_ledger_len);
+        self.journal.truncate(checkpoint.journal_len);
+        self.audit_trail.truncate(checkpoint.audit_trail_len);
+        self.interest_ledger
+            .truncate(checkpoint.interest_ledger_len);
+        self.events.truncate(checkpoint.events_len);
+
+        Ok(())
+    }
+
+    /// Saves the state of the client in the innermost open batch, if any and unless it was already
+    /// saved, so that rolling the batch back restores it. Called before the client is changed.
+    fn save_client(&mut self, client_id: ClientId) {
+        let Some(checkpoint) = self.batches.last_mut() else {
+            return;
+        };
+        if let Entry::Vacant(entry) = checkpoint.undo.clients.entry(client_id) {
+            entry.insert(ClientState {
+                account: self.clients.get(&client_id).cloned(),
+                mismatches: self.client_mismatches.get(&client_id).copied(),
+                activity: self.client_activity.get(&client_id).cloned(),
+                risk_profile: self.risk_profiles.get(&client_id).cloned(),
+                holds: self.holds.get(&client_id).cloned(),
+            });
+        }
+    }
+
+    /// Like [`Self::save_client`], for a transaction of the history.
+    fn save_transaction(&mut self, key: TransactionKey) {
+        let Some(checkpoint) = self.batches.last_mut() else {
+            return;
+        };
+        checkpoint
+            .undo
+            .transactions
+            .entry(key)
+            .or_insert_with(|| self.transaction_history.get(&key).cloned());
+    }
+
+    /// Processes the transactions in a batch, rolling all of them back if any is rejected.
+    pub fn process_batch(
+        &mut self,
+        transactions: impl IntoIterator<Item = Transaction>,
+    ) -> Result<(), BatchRejected> {
+        self.begin_batch();
+        for (index, transaction) in transactions.into_iter().enumerate() {
+            if let Err(error) = self.process_transaction(transaction) {
+                // The batch was begun above, so there is one to roll back
+                let _ = self.rollback();
+                return Err(BatchRejected { index, error });
+            }
         }
+        let _ = self.commit();
+
+        Ok(())
+    }
 
+    /// Applies a transaction that passed the limits and risk rules to the accounts.
+    fn apply_transaction(
+        &mut self,
+        transaction: Transaction,
+        now: Timestamp,
+    ) -> Result<(), ProcessingError> {
+        let (client_id, tx_type, amount) = (
+            transaction.client,
+            transaction.tx_type.clone(),
+            transaction.amount,
+        );
         match transaction.tx_type {
             Deposit => self.process_deposit(transaction),
             Withdrawal => self.process_withdrawal(transaction),
             Dispute => self.process_dispute(transaction),
             Resolve => self.process_resolve(transaction),
             Chargeback => self.process_chargeback(transaction),
+            Authorize => self.process_authorize(transaction, now),
+            Capture => self.process_capture(transaction),
+            Void => self.process_void(transaction),
+            Refund => self.process_refund(transaction),
+            Interest => Err(SystemTransaction {
+                client: transaction.client,
+                tx: transaction.tx,
+                tx_type: Interest,
+            }),
+        }?;
+
+        if let Some(amount) = amount {
+            let activity = self.client_activity.entry(client_id).or_default();
+            match tx_type {
+                Deposit => activity.record_deposit(now),
+                Withdrawal | Authorize => activity.record_withdrawal(amount.value(), now),
+                _ => {}
+            }
+        }
+
+        let profile = self.risk_profiles.entry(client_id).or_default();
+        match tx_type {
+            Deposit => profile.last_deposit_at = Some(now),
+            Dispute => profile.dispute_count += 1,
+            _ => {}
         }
+
+        Ok(())
     }
 
-    fn process_deposit(&mut self, mut transaction: Transaction) -> Result<(), ProcessingError> {
-        let amount = transaction.amount.ok_or(ProcessingError::MissingAmount)?;
+    /// Runs every risk rule, recording the ones that were triggered in the audit trail. The
+    /// transaction is rejected by the first rule that denies it, but every rule is still recorded.
+    fn check_risk_rules(
+        &mut self,
+        transaction: &Transaction,
+        now: Timestamp,
+    ) -> Result<(), ProcessingError> {
+        if self.risk_rules.is_empty() {
+            return Ok(());
+        }
 
         // Safe to unwrap as client has already been created in the main method
-        let client = self.clients.get_mut(&transaction.client).unwrap();
-        client.available_balance = client
-            .available_balance
-            .checked_add(amount.value())
-            .ok_or(BalanceOverflow)?;
-        transaction.tx_status = Settled;
+        let account = self.clients.get(&transaction.client).unwrap();
+        let profile = self
+            .risk_profiles
+            .get(&transaction.client)
+            .cloned()
+            .unwrap_or_default();
+        let context = RiskContext {
+            account,
+            profile: &profile,
+            now,
+        };
+
+        let triggered: Vec<AuditEntry> = self
+            .risk_rules
+            .iter()
+            .map(|rule| (rule.name(), rule.evaluate(transaction, &context)))
+            .filter(|(_, outcome)| *outcome != RiskOutcome::Pass)
+            .map(|(rule, outcome)| AuditEntry {
+                client: transaction.client,
+                tx: transaction.tx,
+                rule: rule.to_string(),
+                outcome,
+            })
+            .collect();
+
+        let mut rejection = None;
+        for entry in triggered {
+            match &entry.outcome {
+                RiskOutcome::Flag { .. } => {
+                    // Safe to unwrap as client has already been created in the main method
+                    self.clients.get_mut(&transaction.client).unwrap().flagged = true;
+                    self.record(Event::AccountFlagged {
+                        client: transaction.client,
+                    });
+                }
+                RiskOutcome::Deny { reason } if rejection.is_none() => {
+                    rejection = Some(RiskRejected {
+                        client: transaction.client,
+                        tx: transaction.tx,
+                        rule: entry.rule.clone(),
+                        reason: reason.clone(),
+                    });
+                }
+                _ => {}
+            }
+            self.audit_trail.push(entry);
+        }
+
+        rejection.map_or(Ok(()), Err)
+    }
+
+    fn check_limits(
+        &mut self,
+        transaction: &Transaction,
+        now: Timestamp,
+    ) -> Result<(), ProcessingError> {
+        let Some(amount) = transaction.amount else {
+            // Nothing to check, the transaction will be rejected for its missing amount
+            return Ok(());
+        };
+
+        let limits = self.limits.limits_for(transaction.client);
+        let activity = self.client_activity.entry(transaction.client).or_default();
+        let result = match transaction.tx_type {
+            Deposit => activity.check_deposit(limits, now),
+            // Authorized funds are bound to be spent, so they count towards the withdrawal limits
+            Withdrawal | Authorize => activity.check_withdrawal(limits, amount.value(), now),
+            Dispute | Resolve | Chargeback | Capture | Void | Refund | Interest => Ok(()),
+        };
+
+        result.map_err(|limit| LimitExceeded {
+            client: transaction.client,
+            tx: transaction.tx,
+            limit,
+        })
+    }
+
+    /// Creates the client's account if it doesn't exist yet, and checks that it can be operated on.
+    pub(crate) fn open_account(&mut self, client_id: ClientId) -> Result<(), ProcessingError> {
+        self.save_client(client_id);
+        if let Entry::Vacant(entry) = self.clients.entry(client_id) {
+            entry.insert(ClientAccount::default());
+            self.record(Event::AccountOpened { client: client_id });
+        }
+
+        // Safe to unwrap as client was created above
+        let client = self.clients.get(&client_id).unwrap();
+        if client.locked {
+            return Err(AccountLocked { client: client_id });
+        }
 
-        self.transaction_history.insert(transaction.tx, transaction);
+        Ok(())
+    }
+
+    /// Checks that the referenced transaction exists and belongs to the given client.
+    fn check_transaction_owner(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+    ) -> Result<(), ProcessingError> {
+        let owner = self
+            .transaction(client_id, tx)
+            .ok_or(TransactionNotFound {
+                client: client_id,
+                tx,
+            })?
+            .client;
+
+        if owner != client_id {
+            self.record_client_mismatch(client_id);
+            return Err(ClientMismatch {
+                client: client_id,
+                tx,
+                owner,
+            });
+        }
 
         Ok(())
     }
 
+    /// Counts an attempt by the client to reference another client's transaction, and applies the
+    /// [`ClientMismatchPolicy`] once the threshold is reached.
+    pub(crate) fn record_client_mismatch(&mut self, client_id: ClientId) {
+        self.save_client(client_id);
+        self.record(Event::ClientMismatch { client: client_id });
+        let count = self.client_mismatches.entry(client_id).or_default();
+        *count += 1;
+
+        let Some(policy) = self.client_mismatch_policy else {
+            return;
+        };
+        if *count < policy.threshold {
+            return;
+        }
+
+        let client = self.clients.entry(client_id).or_default();
+        match policy.action {
+            ClientMismatchAction::Flag => client.flagged = true,
+            ClientMismatchAction::Lock => client.locked = true,
+        }
+    }
+
+    fn process_deposit(&mut self, mut transaction: Transaction) -> Result<(), ProcessingError> {
+        let (client_id, tx) = (transaction.client, transaction.tx);
+        let amount = transaction.amount.ok_or(MissingAmount {
+            client: client_id,
+            tx,
+        })?;
+
+        // The deposit fee is taken out of the deposited funds, so it can't exceed them
+        let fee = self
+            .fee_schedule
+            .fee_for(FeeKind::Deposit, amount.value(), &self.precision)
+            .min(amount.value());
+        self.check_fee(client_id, tx, fee)?;
+        self.transfer(
+            EntryKind::Deposit,
+            tx,
+            LedgerAccount::HouseClearing,
+            LedgerAccount::ClientAvailable(client_id),
+            amount.value(),
+        )?;
+
+        transaction.tx_status = Settled;
+        self.insert_transaction(transaction);
+        self.book_fee(client_id, tx, FeeKind::Deposit, fee)
+    }
+
     fn process_withdrawal(&mut self, mut transaction: Transaction) -> Result<(), ProcessingError> {
-        let amount = transaction.amount.ok_or(ProcessingError::MissingAmount)?;
+        let (client_id, tx) = (transaction.client, transaction.tx);
+        let amount = transaction.amount.ok_or(MissingAmount {
+            client: client_id,
+            tx,
+        })?;
+
+        let fee = self
+            .fee_schedule
+            .fee_for(FeeKind::Withdrawal, amount.value(), &self.precision);
+        let requested = amount.value().checked_add(fee).ok_or(BalanceOverflow {
+            client: client_id,
+            tx,
+        })?;
+
+        // Withdrawals may draw on the client's credit line, if it has one
+        let overdraft = self.limits.overdraft_for(client_id);
 
         // Safe to unwrap as client has already been created in the main method
-        let client = self.clients.get_mut(&transaction.client).unwrap();
-        if client.available_balance < amount.value() {
-            return Err(InsufficientFunds);
+        let client = self.clients.get(&client_id).unwrap();
+        let available = client.available_balance.saturating_add(overdraft);
+        if available < requested {
+            return Err(InsufficientFunds {
+                client: client_id,
+                tx,
+                requested,
+                available,
+            });
         }
 
-        client.available_balance -= amount.value();
+        self.check_fee(client_id, tx, fee)?;
+        self.transfer(
+            EntryKind::Withdrawal,
+            tx,
+            LedgerAccount::ClientAvailable(client_id),
+            LedgerAccount::HouseClearing,
+            amount.value(),
+        )?;
+
         transaction.tx_status = Settled;
+        self.insert_transaction(transaction);
+        self.book_fee(client_id, tx, FeeKind::Withdrawal, fee)
+    }
+
+    fn process_dispute(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
+        let (client_id, tx) = (transaction.client, transaction.tx);
+        self.check_transaction_owner(client_id, tx)?;
+        // Safe to unwrap as the original transaction was found above
+        let original_tx = self.transaction(client_id, tx).unwrap();
+
+        // Disputes are only possible against Deposit transactions
+        // A dispute can only be opened on a transaction that is settled, or that has had disputes that have since been resolved
+        if !matches!(original_tx.tx_type, Deposit)
+            || !matches!(original_tx.tx_status, Settled | Resolved)
+        {
+            return Err(InvalidDispute {
+                client: client_id,
+                tx,
+                tx_type: original_tx.tx_type.clone(),
+                tx_status: original_tx.tx_status.clone(),
+            });
+        }
+
+        let original_amount = original_tx.unrefunded_amount().ok_or(MissingAmount {
+            client: client_id,
+            tx,
+        })?;
+        // Safe to unwrap as client is guaranteed to exist at this point
+        let client = self.clients.get(&client_id).unwrap();
+        // Disputed funds must be covered by the client's own funds, never by its credit line, so a
+        // dispute can't open or deepen an overdraft unless the policy allows negative balances
+        if client.available_balance < original_amount
+            && self.negative_balance_policy == NegativeBalancePolicy::Refuse
+        {
+            return Err(InsufficientFunds {
+                client: client_id,
+                tx,
+                requested: original_amount,
+                available: client.available_balance,
+            });
+        }
 
-        self.transaction_history.insert(transaction.tx, transaction);
+        self.transfer(
+            EntryKind::Dispute,
+            tx,
+            LedgerAccount::ClientAvailable(client_id),
+            LedgerAccount::ClientHeld(client_id),
+            original_amount,
+        )?;
+        self.set_status(client_id, tx, Disputed);
 
         Ok(())
     }
 
-    fn process_dispute(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
-        let original_tx = self
-            .transaction_history
-            .get_mut(&transaction.tx)
-            .ok_or(TransactionNotFound)?;
+    fn process_resolve(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
+        let (client_id, tx) = (transaction.client, transaction.tx);
+        self.check_transaction_owner(client_id, tx)?;
+        // Safe to unwrap as the original transaction was found above
+        let original_tx = self.transaction(client_id, tx).unwrap();
 
-        if transaction.client != original_tx.client {
-            return Err(TransactionNotFound);
+        if !matches!(original_tx.tx_status, Disputed) {
+            return Err(InvalidTransactionStatus {
+                client: client_id,
+                tx,
+                expected: Disputed,
+                actual: original_tx.tx_status.clone(),
+            });
         }
 
-        // Disputes are only possible against Deposit transactions
-        if !matches!(original_tx.tx_type, Deposit) {
-            return Err(InvalidDispute);
+        let original_amount = original_tx.unrefunded_amount().ok_or(MissingAmount {
+            client: client_id,
+            tx,
+        })?;
+        self.transfer(
+            EntryKind::Resolve,
+            tx,
+            LedgerAccount::ClientHeld(client_id),
+            LedgerAccount::ClientAvailable(client_id),
+            original_amount,
+        )?;
+        self.set_status(client_id, tx, Resolved);
+
+        Ok(())
+    }
+
+    fn process_chargeback(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
+        let (client_id, tx) = (transaction.client, transaction.tx);
+        self.check_transaction_owner(client_id, tx)?;
+        // Safe to unwrap as the original transaction was found above
+        let original_tx = self.transaction(client_id, tx).unwrap();
+
+        if !matches!(original_tx.tx_status, Disputed) {
+            return Err(InvalidTransactionStatus {
+                client: client_id,
+                tx,
+                expected: Disputed,
+                actual: original_tx.tx_status.clone(),
+            });
         }
 
-        // A dispute can only be opened on a transaction that is settled, or that has had disputes that have since been resolved
-        if !matches!(original_tx.tx_status, Settled | Resolved) {
-            return Err(InvalidDispute);
+        let original_amount = original_tx.unrefunded_amount().ok_or(MissingAmount {
+            client: client_id,
+            tx,
+        })?;
+        // Safe to unwrap as client is guaranteed to exist at this point
+        let client = self.clients.get(&client_id).unwrap();
+
+        // The chargeback fee can only be collected from what is left in the account
+        let fee = self
+            .fee_schedule
+            .fee_for(FeeKind::Chargeback, original_amount, &self.precision)
+            .min(client.available_balance.max(Decimal::ZERO));
+        self.check_fee(client_id, tx, fee)?;
+        self.transfer(
+            EntryKind::Chargeback,
+            tx,
+            LedgerAccount::ClientHeld(client_id),
+            LedgerAccount::HouseClearing,
+            original_amount,
+        )?;
+
+        // Safe to unwrap as client is guaranteed to exist at this point
+        self.clients.get_mut(&client_id).unwrap().locked = true;
+        self.set_status(client_id, tx, ChargedBack);
+        self.book_fee(client_id, tx, FeeKind::Chargeback, fee)
+    }
+
+    fn process_refund(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
+        let (client_id, tx) = (transaction.client, transaction.tx);
+        let amount = transaction
+            .amount
+            .ok_or(MissingAmount {
+                client: client_id,
+                tx,
+            })?
+            .value();
+        self.check_transaction_owner(client_id, tx)?;
+        // Safe to unwrap as the original transaction was found above
+        let original_tx = self.transaction(client_id, tx).unwrap();
+
+        // Only deposits can be refunded, and not while they are disputed or once charged back
+        if !matches!(original_tx.tx_type, Deposit)
+            || !matches!(original_tx.tx_status, Settled | Resolved)
+        {
+            return Err(InvalidRefund {
+                client: client_id,
+                tx,
+                tx_type: original_tx.tx_type.clone(),
+                tx_status: original_tx.tx_status.clone(),
+            });
+        }
+
+        let refundable = original_tx.unrefunded_amount().ok_or(MissingAmount {
+            client: client_id,
+            tx,
+        })?;
+        if amount > refundable {
+            return Err(RefundTooLarge {
+                client: client_id,
+                tx,
+                requested: amount,
+                refundable,
+            });
         }
 
         // Safe to unwrap as client is guaranteed to exist at this point
-        let client = self.clients.get_mut(&transaction.client).unwrap();
+        let client = self.clients.get(&client_id).unwrap();
+        if client.available_balance < amount {
+            return Err(InsufficientFunds {
+                client: client_id,
+                tx,
+                requested: amount,
+                available: client.available_balance,
+            });
+        }
+
+        self.transfer(
+            EntryKind::Refund,
+            tx,
+            LedgerAccount::ClientAvailable(client_id),
+            LedgerAccount::HouseClearing,
+            amount,
+        )?;
+
+        let key = self.key(client_id, tx);
+        self.save_transaction(key);
+        // Safe to unwrap as the original transaction was found above
+        let original_tx = self.transaction_history.get_mut(&key).unwrap();
+        // Can't overflow, as the refunded amount never exceeds the deposited one
+        original_tx.refunded += amount;
+        if original_tx.unrefunded_amount() == Some(Decimal::ZERO) {
+            original_tx.tx_status = Refunded;
+        }
+
+        Ok(())
+    }
 
-        let original_amount = original_tx.amount.ok_or(MissingAmount)?.value();
-        if client.available_balance < original_amount {
-            return Err(InsufficientFunds);
+    fn process_authorize(
+        &mut self,
+        mut transaction: Transaction,
+        now: Timestamp,
+    ) -> Result<(), ProcessingError> {
+        let (client_id, tx) = (transaction.client, transaction.tx);
+        let amount = transaction
+            .amount
+            .ok_or(MissingAmount {
+                client: client_id,
+                tx,
+            })?
+            .value();
+
+        // Authorizations may draw on the client's credit line, like withdrawals
+        let overdraft = self.limits.overdraft_for(client_id);
+
+        // Safe to unwrap as client has already been created in the main method
+        let client = self.clients.get(&client_id).unwrap();
+        let available = client.available_balance.saturating_add(overdraft);
+        if available < amount {
+            return Err(InsufficientFunds {
+                client: client_id,
+                tx,
+                requested: amount,
+                available,
+            });
         }
 
-        client.held_balance = client
-            .held_balance
-            .checked_add(original_amount)
-            .ok_or(BalanceOverflow)?;
-        client.available_balance -= original_amount;
+        self.transfer(
+            EntryKind::Authorize,
+            tx,
+            LedgerAccount::ClientAvailable(client_id),
+            LedgerAccount::ClientHeld(client_id),
+            amount,
+        )?;
 
-        original_tx.tx_status = Disputed;
+        transaction.tx_status = Authorized;
+        self.insert_transaction(transaction);
+        self.holds.entry(client_id).or_default().push((now, tx));
 
         Ok(())
     }
 
-    fn process_resolve(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
-        let original_tx = self
-            .transaction_history
-            .get_mut(&transaction.tx)
-            .ok_or(TransactionNotFound)?;
+    fn process_capture(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
+        let (client_id, tx) = (transaction.client, transaction.tx);
+        let authorized = self.take_authorized_amount(client_id, tx)?;
+
+        // Captures without an amount settle the whole authorized amount
+        let captured = transaction
+            .amount
+            .map_or(authorized, |amount| amount.value());
+        if captured > authorized {
+            return Err(InsufficientFunds {
+                client: client_id,
+                tx,
+                requested: captured,
+                available: authorized,
+            });
+        }
+
+        // The captured amount leaves the system, and the rest goes back to the client
+        self.post(JournalEntry {
+            kind: EntryKind::Capture,
+            tx: Some(tx),
+            postings: vec![
+                Posting {
+                    account: LedgerAccount::ClientHeld(client_id),
+                    amount: -authorized,
+                },
+                Posting {
+                    account: LedgerAccount::ClientAvailable(client_id),
+                    amount: authorized - captured,
+                },
+                Posting {
+                    account: LedgerAccount::HouseClearing,
+                    amount: captured,
+                },
+            ],
+        })?;
+        self.close_hold(client_id, tx, Captured);
 
-        if original_tx.client != transaction.client {
-            return Err(TransactionNotFound);
+        Ok(())
+    }
+
+    fn process_void(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
+        let (client_id, tx) = (transaction.client, transaction.tx);
+        self.take_authorized_amount(client_id, tx)?;
+        self.release_hold(client_id, tx, Voided)
+    }
+
+    /// Checks that the referenced transaction is an authorization of the client whose funds are
+    /// still held, and returns the authorized amount.
+    fn take_authorized_amount(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+    ) -> Result<Decimal, ProcessingError> {
+        self.check_transaction_owner(client_id, tx)?;
+        // Safe to unwrap as the original transaction was found above
+        let original_tx = self.transaction(client_id, tx).unwrap();
+
+        if !matches!(original_tx.tx_status, Authorized) {
+            return Err(InvalidTransactionStatus {
+                client: client_id,
+                tx,
+                expected: Authorized,
+                actual: original_tx.tx_status.clone(),
+            });
         }
 
-        if !matches!(original_tx.tx_status, Disputed) {
-            return Err(InvalidTransactionStatus);
+        Ok(original_tx
+            .amount
+            .ok_or(MissingAmount {
+                client: client_id,
+                tx,
+            })?
+            .value())
+    }
+
+    /// Moves the authorized amount back from held to available, and closes the hold. The hold is
+    /// left open if the available balance would overflow.
+    fn release_hold(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+        status: TransactionStatus,
+    ) -> Result<(), ProcessingError> {
+        // Safe to unwrap as only authorizations with an amount are ever held
+        let amount = self.transaction_history[&self.key(client_id, tx)]
+            .amount
+            .unwrap()
+            .value();
+
+        let kind = match status {
+            Expired => EntryKind::HoldExpiry,
+            _ => EntryKind::Void,
+        };
+        self.transfer(
+            kind,
+            tx,
+            LedgerAccount::ClientHeld(client_id),
+            LedgerAccount::ClientAvailable(client_id),
+            amount,
+        )?;
+        self.close_hold(client_id, tx, status);
+
+        Ok(())
+    }
+
+    fn close_hold(&mut self, client_id: ClientId, tx: TransactionId, status: TransactionStatus) {
+        self.set_status(client_id, tx, status);
+        if let Some(holds) = self.holds.get_mut(&client_id) {
+            holds.retain(|(_, held_tx)| *held_tx != tx);
+        }
+    }
+
+    fn set_status(&mut self, client_id: ClientId, tx: TransactionId, status: TransactionStatus) {
+        let key = self.key(client_id, tx);
+        self.save_transaction(key);
+        if let Some(original_tx) = self.transaction_history.get_mut(&key) {
+            original_tx.tx_status = status;
+        }
+    }
+
+    /// Releases the client's holds that are older than the hold expiry.
+    fn expire_client_holds(&mut self, client_id: ClientId, now: Timestamp) {
+        let Some(expiry) = self.hold_expiry else {
+            return;
+        };
+        self.save_client(client_id);
+
+        let expired: Vec<TransactionId> = self
+            .holds
+            .get(&client_id)
+            .into_iter()
+            .flatten()
+            .take_while(|(authorized_at, _)| authorized_at.saturating_add(expiry) <= now)
+            .map(|(_, tx)| *tx)
+            .collect();
+        let mut released = false;
+        for tx in expired {
+            // A hold that can't be released without overflowing stays open until it can
+            released |= self.release_hold(client_id, tx, Expired).is_ok();
+        }
+        if !released {
+            return;
+        }
+        self.record(Event::HoldsExpired {
+            client: client_id,
+            at: now,
+        });
+    }
+
+    /// Releases every hold that expired by now. Holds are otherwise only expired when their client
+    /// sends a new transaction, so this should be called before reporting balances.
+    pub fn expire_holds(&mut self) {
+        let now = self.clock.now();
+        let mut clients: Vec<ClientId> = self.holds.keys().copied().collect();
+        clients.sort_by_key(|client_id| client_id.value());
+        for client_id in clients {
+            self.expire_client_holds(client_id, now);
         }
+    }
+
+    fn record(&mut self, event: Event) {
+        self.events.push(EventRecord {
+            sequence: self.sequence,
+            event,
+        });
+    }
+
+    /// Every change made to the engine so far, in the order it was made.
+    pub fn events(&self) -> &[EventRecord] {
+        &self.events
+    }
+
+    /// Number of input rows seen so far, whether their transaction was applied, rejected or
+    /// couldn't be parsed.
+    pub fn sequence(&self) -> SequenceNumber {
+        self.sequence
+    }
+
+    /// Counts an input row that couldn't be parsed into a transaction, so that sequence numbers
+    /// keep matching input rows.
+    pub fn skip_row(&mut self) {
+        self.sequence += 1;
+    }
+
+    /// Rebuilds the engine as it was right after processing the input row with the given sequence
+    /// number, by folding its events into an engine with the same configuration.
+    ///
+    /// The rebuilt engine uses the system clock and no risk rules, as their effects are part of
+    /// the events, and has an empty audit trail.
+    pub fn state_at(&self, sequence: SequenceNumber) -> Result<PaymentsEngine, ReplayFailed> {
+        let mut engine = Self::new()
+            .with_fee_schedule(self.fee_schedule.clone())
+            .with_limits(self.limits.clone())
+            .with_interest(self.interest.clone())
+            .with_negative_balance_policy(self.negative_balance_policy)
+            .with_precision(self.precision)
+            .with_id_namespace(self.id_namespace);
+        engine.client_mismatch_policy = self.client_mismatch_policy;
+        engine.hold_expiry = self.hold_expiry;
+
+        for record in self
+            .events
+            .iter()
+            .take_while(|record| record.sequence <= sequence)
+        {
+            engine.apply_event(record)?;
+        }
+        // Rows without events, e.g. malformed ones, still count
+        engine.sequence = sequence.min(self.sequence);
+
+        Ok(engine)
+    }
+
+    /// Rebuilds the engine from all of its events.
+    pub fn replay(&self) -> Result<PaymentsEngine, ReplayFailed> {
+        self.state_at(self.sequence)
+    }
+
+    fn apply_event(&mut self, record: &EventRecord) -> Result<(), ReplayFailed> {
+        self.sequence = record.sequence;
+        match &record.event {
+            Event::AccountOpened { client } => {
+                // A new account is never locked
+                let _ = self.open_account(*client);
+            }
+            Event::TransactionApplied { transaction, at } => {
+                self.apply_transaction(transaction.clone(), *at)
+                    .map_err(|error| ReplayFailed {
+                        sequence: record.sequence,
+                        error,
+                    })?;
+                self.record(record.event.clone());
+            }
+            Event::TransactionRejected { transaction, error } => {
+                self.remember_rejection(transaction.clone(), error.clone());
+                self.record(record.event.clone());
+            }
+            Event::ClientMismatch { client } => self.record_client_mismatch(*client),
+            Event::AccountFlagged { client } => {
+                self.clients.entry(*client).or_default().flagged = true;
+                self.record(record.event.clone());
+            }
+            Event::HoldsExpired { client, at } => self.expire_client_holds(*client, *at),
+            Event::InterestAccrued { period } => {
+                self.accrue_interest(*period);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Posts an entry moving the amount between two accounts, see [`Self::post`].
+    fn transfer(
+        &mut self,
+        kind: EntryKind,
+        tx: TransactionId,
+        from: LedgerAccount,
+        to: LedgerAccount,
+        amount: Decimal,
+    ) -> Result<(), ProcessingError> {
+        self.post(JournalEntry::transfer(kind, Some(tx), from, to, amount))
+    }
+
+    /// Posts an entry to the journal and applies it to the client accounts it touches, which is
+    /// the only way their balances change. Nothing changes if any of the balances, in the client
+    /// accounts or in the journal, would overflow.
+    fn post(&mut self, entry: JournalEntry) -> Result<(), ProcessingError> {
+        // Safe to unwrap as the engine only posts entries for a transaction of a client
+        let overflow = BalanceOverflow {
+            client: entry
+                .postings
+                .iter()
+                .find_map(|posting| posting.account.client())
+                .unwrap(),
+            tx: entry.tx.unwrap(),
+        };
+
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        for posting in &entry.postings {
+            let (client_id, available) = match posting.account {
+                LedgerAccount::ClientAvailable(client_id) => (client_id, true),
+                LedgerAccount::ClientHeld(client_id) => (client_id, false),
+                LedgerAccount::HouseClearing
+                | LedgerAccount::HouseFees
+                | LedgerAccount::HouseInterest => continue,
+            };
+            let account = accounts
+                .entry(client_id)
+                .or_insert_with(|| self.clients.get(&client_id).cloned().unwrap_or_default());
+            let balance = if available {
+                &mut account.available_balance
+            } else {
+                &mut account.held_balance
+            };
+            *balance = balance
+                .checked_add(posting.amount)
+                .ok_or_else(|| overflow.clone())?;
+        }
+        self.journal.post(entry).map_err(|_| overflow)?;
+        for client_id in accounts.keys() {
+            self.save_client(*client_id);
+        }
+        self.clients.extend(accounts);
+
+        Ok(())
+    }
+
+    /// Checks that the house fee account can take the fee, so that booking it can't fail once the
+    /// transaction it is charged for has been posted.
+    fn check_fee(
+        &self,
+        client_id: ClientId,
+        tx: TransactionId,
+        fee: Decimal,
+    ) -> Result<(), ProcessingError> {
+        self.journal
+            .balance(LedgerAccount::HouseFees)
+            .checked_add(fee)
+            .map(|_| ())
+            .ok_or(BalanceOverflow {
+                client: client_id,
+                tx,
+            })
+    }
+
+    /// Moves a fee from the client's available balance to the house fee account. The caller must
+    /// have checked that the client can afford it.
+    fn book_fee(
+        &mut self,
+        client_id: ClientId,
+        tx: TransactionId,
+        kind: FeeKind,
+        fee: Decimal,
+    ) -> Result<(), ProcessingError> {
+        if fee.is_zero() {
+            return Ok(());
+        }
+
+        self.transfer(
+            EntryKind::Fee(kind),
+            tx,
+            LedgerAccount::ClientAvailable(client_id),
+            LedgerAccount::HouseFees,
+            fee,
+        )?;
+        // Safe to unwrap as client is guaranteed to exist at this point
+        let client = self.clients.get_mut(&client_id).unwrap();
+        client.fees = client.fees.saturating_add(fee);
+
+        self.fee_ledger.push(FeeEntry {
+            client: client_id,
+            tx,
+            kind,
+            amount: fee,
+        });
+
+        Ok(())
+    }
+
+    /// Credits every account with the interest earned over the period, and returns the postings.
+    /// Each posting is an [`Interest`] transaction with an ID generated by the engine, see
+    /// [`Self::system_transaction`]. Locked accounts don't earn interest.
+    ///
+    /// Each period is only accrued once, so accruing it again posts nothing. Periods are expected
+    /// not to overlap, as that would pay interest twice for the overlapping days.
+    pub fn accrue_interest(&mut self, period: InterestPeriod) -> &[InterestEntry] {
+        let first_posting = self.interest_ledger.len();
+        if !self.accrued_periods.insert(period) {
+            return &[];
+        }
+        if let Some(checkpoint) = self.batches.last_mut() {
+            checkpoint.undo.accrued_periods.push(period);
+        }
+        self.record(Event::InterestAccrued { period });
+
+        let mut postings: Vec<(ClientId, Decimal)> = self
+            .clients
+            .iter()
+            .filter(|(_, account)| !account.locked)
+            .filter_map(|(client_id, account)| {
+                let balance = if self.interest.include_held {
+                    account.total()
+                } else {
+                    account.available_balance
+                };
+                let interest =
+                    self.interest
+                        .interest_for(*client_id, balance, &period, &self.precision);
+                (!interest.is_zero()).then_some((*client_id, interest))
+            })
+            .collect();
+        // Post in client order, so that replaying the events books the same entries
+        postings.sort_by_key(|(client_id, _)| client_id.value());
+
+        for (client_id, interest) in postings {
+            let tx = self.next_system_tx();
+            let posted = self.transfer(
+                EntryKind::Interest,
+                tx,
+                LedgerAccount::HouseInterest,
+                LedgerAccount::ClientAvailable(client_id),
+                interest,
+            );
+            // Accounts whose balance would overflow don't get any interest
+            if posted.is_err() {
+                continue;
+            }
+
+            // Safe to unwrap as interest is always positive here
+            let amount = Some(Amount::new(interest).unwrap());
+            let transaction = Transaction {
+                tx_type: Interest,
+                client: client_id,
+                tx,
+                amount,
+                submitted_amount: amount,
+                tx_status: Settled,
+                refunded: Decimal::ZERO,
+            };
+            self.save_transaction(TransactionKey::System(tx));
+            self.transaction_history
+                .insert(TransactionKey::System(tx), transaction);
+            self.interest_ledger.push(InterestEntry {
+                client: client_id,
+                tx,
+                start: period.start,
+                end: period.end,
+                amount: interest,
+            });
+        }
+
+        &self.interest_ledger[first_posting..]
+    }
+
+    /// Double-entry journal the client balances are projected from.
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    /// Takes the ID of the next transaction generated by the engine. IDs count down from
+    /// `u32::MAX`, so that they stand out from the ones clients usually use in statements.
+    fn next_system_tx(&mut self) -> TransactionId {
+        let tx = TransactionId::new(self.next_system_tx);
+        self.next_system_tx = self.next_system_tx.wrapping_sub(1);
+        tx
+    }
+
+    /// All interest posted so far, in the order it was posted.
+    pub fn interest_entries(&self) -> &[InterestEntry] {
+        &self.interest_ledger
+    }
+
+    pub fn id_namespace(&self) -> IdNamespace {
+        self.id_namespace
+    }
+
+    /// Deposit, withdrawal or authorization with the given ID, as seen by the given client.
+    pub fn transaction(&self, client_id: ClientId, tx: TransactionId) -> Option<&Transaction> {
+        self.transaction_history.get(&self.key(client_id, tx))
+    }
+
+    /// Transaction generated by the engine with the given ID, e.g. an interest posting.
+    pub fn system_transaction(&self, tx: TransactionId) -> Option<&Transaction> {
+        self.transaction_history.get(&TransactionKey::System(tx))
+    }
+
+    pub fn precision(&self) -> &Precision {
+        &self.precision
+    }
+
+    pub fn client_accounts(&self) -> &HashMap<ClientId, ClientAccount> {
+        &self.clients
+    }
+
+    /// Locked accounts whose total balance is negative, i.e. clients that owe money after a
+    /// chargeback and need to go through collections.
+    pub fn collections(&self) -> impl Iterator<Item = (&ClientId, &ClientAccount)> {
+        self.clients
+            .iter()
+            .filter(|(_, account)| account.locked && account.total() < Decimal::ZERO)
+    }
+
+    /// All fees charged so far, in the order they were booked.
+    pub fn fee_entries(&self) -> &[FeeEntry] {
+        &self.fee_ledger
+    }
+
+    /// Fees charged to a single client, in the order they were booked.
+    pub fn client_fee_entries(&self, client_id: ClientId) -> impl Iterator<Item = &FeeEntry> {
+        self.fee_ledger
+            .iter()
+            .filter(move |entry| entry.client == client_id)
+    }
+
+    /// Every risk rule triggered so far, in the order they were triggered.
+    pub fn audit_trail(&self) -> &[AuditEntry] {
+        &self.audit_trail
+    }
+
+    /// Total of all fees collected by the house.
+    pub fn house_fees(&self) -> Decimal {
+        self.journal.balance(LedgerAccount::HouseFees)
+    }
+
+    /// Number of times the client referenced a transaction belonging to another client.
+    pub fn client_mismatch_count(&self, client_id: ClientId) -> u32 {
+        self.client_mismatches
+            .get(&client_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    #[cfg(test)]
+    pub fn lock_account(&mut self, client_id: ClientId) {
+        if let Some(account) = self.clients.get_mut(&client_id) {
+            account.locked = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::domain::TransactionStatus;
+    use crate::fees::FeeRule;
+    use crate::interest::{Date, DayCount};
+    use crate::limits::Limits;
+    use crate::precision::Rounding;
+    use crate::risk::{
+        DenyListRule, DisputeCountRule, LargeAmountRule, RapidWithdrawalRule, RiskAction,
+    };
+    use crate::test_utils::create_transaction;
+    use rust_decimal::dec;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_payments_engine_new() {
+        let engine = PaymentsEngine::new();
+        assert!(engine.clients.is_empty());
+        assert!(engine.transaction_history.is_empty());
+        assert!(engine.client_accounts().is_empty());
+    }
+
+    #[test]
+    fn test_client_account_total() {
+        let mut account = ClientAccount::default();
+        assert_eq!(account.total(), Decimal::ZERO);
+
+        account.available_balance = Decimal::TEN;
+        account.held_balance = dec!(5);
+        assert_eq!(account.total(), dec!(15));
+    }
+
+    #[test]
+    fn test_client_account_default() {
+        let account = ClientAccount::default();
+        assert_eq!(account.available_balance, Decimal::ZERO);
+        assert_eq!(account.held_balance, Decimal::ZERO);
+        assert!(!account.locked);
+        assert!(!account.flagged);
+    }
+
+    #[test]
+    fn test_deposit_happy_path() {
+        let mut engine = PaymentsEngine::new();
+        let transaction = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+
+        let result = engine.process_transaction(transaction);
+
+        assert!(result.is_ok());
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+        assert_eq!(client_account.total(), Decimal::TEN);
+        assert!(!client_account.locked);
+    }
+
+    #[test]
+    fn test_deposit_multiple_deposits_same_client() {
+        let mut engine = PaymentsEngine::new();
+        let tx1 = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let tx2 = create_transaction(Deposit, 1, 2, Some(dec!(5)));
+
+        engine.process_transaction(tx1).unwrap();
+        engine.process_transaction(tx2).unwrap();
+
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(15));
+        assert_eq!(client_account.total(), dec!(15));
+    }
+
+    #[test]
+    fn test_deposit_missing_amount() {
+        let mut engine = PaymentsEngine::new();
+        let transaction = create_transaction(Deposit, 1, 1, None);
+
+        let result = engine.process_transaction(transaction);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::MissingAmount {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1)
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_deposit_new_client_creation() {
+        let mut engine = PaymentsEngine::new();
+        let transaction = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+
+        engine.process_transaction(transaction).unwrap();
+
+        assert_eq!(engine.clients.len(), 1);
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+        assert!(!client_account.locked);
+    }
+
+    #[test]
+    fn test_deposit_decimal_precision() {
+        let mut engine = PaymentsEngine::new();
+        let amount = dec!(12.3456);
+        let transaction = create_transaction(Deposit, 1, 1, Some(amount));
+
+        engine.process_transaction(transaction).unwrap();
+
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, amount);
+    }
+
+    #[test]
+    fn test_deposit_large_amount() {
+        let mut engine = PaymentsEngine::new();
+        let large_amount = Decimal::MAX;
+        let transaction = create_transaction(Deposit, 1, 1, Some(large_amount));
+
+        let result = engine.process_transaction(transaction);
+
+        assert!(result.is_ok());
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, large_amount);
+    }
+
+    #[test]
+    fn test_deposit_balance_overflow() {
+        let mut engine = PaymentsEngine::new();
+        let large_amount = Decimal::MAX;
+        let tx1 = create_transaction(Deposit, 1, 1, Some(large_amount));
+        let tx2 = create_transaction(Deposit, 1, 2, Some(dec!(1)));
+
+        let result1 = engine.process_transaction(tx1);
+        let result2 = engine.process_transaction(tx2);
+
+        assert!(result1.is_ok());
+        assert_eq!(
+            result2,
+            Err(ProcessingError::BalanceOverflow {
+                client: ClientId::new(1),
+                tx: TransactionId::new(2)
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, large_amount);
+    }
+
+    #[test]
+    fn test_deposit_house_balance_overflow() {
+        let mut engine = PaymentsEngine::new();
+        let tx1 = create_transaction(Deposit, 1, 1, Some(Decimal::MAX));
+        let tx2 = create_transaction(Deposit, 2, 2, Some(dec!(1)));
+
+        engine.process_transaction(tx1).unwrap();
+        let result = engine.process_transaction(tx2);
+
+        // Client 2's balance can take the deposit, but the clearing account can't
+        assert_eq!(
+            result,
+            Err(ProcessingError::BalanceOverflow {
+                client: ClientId::new(2),
+                tx: TransactionId::new(2)
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(2)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert!(matches!(
+            engine
+                .transaction(ClientId::new(2), TransactionId::new(2))
+                .unwrap()
+                .tx_status,
+            TransactionStatus::Rejected(_)
+        ));
+        assert_eq!(engine.journal().entries().len(), 1);
+        assert_eq!(
+            engine.journal().balance(LedgerAccount::HouseClearing),
+            -Decimal::MAX
+        );
+        assert!(engine.journal().is_balanced());
+    }
+
+    #[test]
+    fn test_deposit_duplicate_deposit_ignored() {
+        let mut engine = PaymentsEngine::new();
+        let tx1 = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+
+        engine.process_transaction(tx1.clone()).unwrap();
+        let result = engine.process_transaction(tx1);
+
+        assert!(result.is_ok());
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::TEN);
+    }
+
+    #[test]
+    fn test_deposit_duplicate_with_other_payload_conflict() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(10.00)));
+        engine.process_transaction(deposit).unwrap();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::ONE));
+        let result = engine.process_transaction(deposit);
+        assert_eq!(
+            result,
+            Err(IdempotencyConflict {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1)
+            })
+        );
+        // Another type makes it another transaction, not a retry
+        let withdrawal = create_transaction(Withdrawal, 1, 1, Some(Decimal::TEN));
+        let result = engine.process_transaction(withdrawal);
+        assert_eq!(
+            result,
+            Err(TransactionIdConflict {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1)
+            })
+        );
+        assert_eq!(
+            engine.clients[&ClientId::new(1)].available_balance,
+            Decimal::TEN
+        );
+    }
+
+    #[test]
+    fn test_retry_returns_original_outcome() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::ONE));
+        engine.process_transaction(deposit).unwrap();
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(5)));
+        let rejected = Err(InsufficientFunds {
+            client: ClientId::new(1),
+            tx: TransactionId::new(2),
+            requested: dec!(5),
+            available: Decimal::ONE,
+        });
+        assert_eq!(engine.process_transaction(withdrawal.clone()), rejected);
+
+        // The retry is rejected for the original reason, even though the funds are now available
+        let deposit = create_transaction(Deposit, 1, 3, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+        assert_eq!(engine.process_transaction(withdrawal.clone()), rejected);
+        assert_eq!(
+            engine.clients[&ClientId::new(1)].available_balance,
+            dec!(11)
+        );
+        // Retries don't record another event
+        let rejections = engine
+            .events()
+            .iter()
+            .filter(|event| matches!(event.event, Event::TransactionRejected { .. }))
+            .count();
+        assert_eq!(rejections, 1);
+
+        // Rejected transactions are rebuilt from the events
+        let mut replayed = engine.replay().unwrap();
+        assert_eq!(replayed.process_transaction(withdrawal), rejected);
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(4)));
+        assert_eq!(
+            replayed.process_transaction(withdrawal),
+            Err(IdempotencyConflict {
+                client: ClientId::new(1),
+                tx: TransactionId::new(2)
+            })
+        );
+    }
+
+    #[test]
+    fn test_global_id_namespace_conflict_across_clients() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(20)));
+        engine.process_transaction(deposit).unwrap();
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(5)));
+        engine.process_transaction(withdrawal).unwrap();
+
+        let deposit = create_transaction(Deposit, 2, 2, Some(dec!(5)));
+        let result = engine.process_transaction(deposit);
+        assert_eq!(
+            result,
+            Err(TransactionIdConflict {
+                client: ClientId::new(2),
+                tx: TransactionId::new(2)
+            })
+        );
+        assert!(!engine.clients.contains_key(&ClientId::new(2)));
+    }
+
+    #[test]
+    fn test_content_hash_id_namespace() {
+        let mut engine = PaymentsEngine::new().with_id_namespace(IdNamespace::ContentHash);
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+        // Same content under another ID is a retry
+        let deposit = create_transaction(Deposit, 1, 2, Some(dec!(10.00)));
+        engine.process_transaction(deposit).unwrap();
+        assert!(
+            engine
+                .transaction(ClientId::new(1), TransactionId::new(2))
+                .is_none()
+        );
+        // Other amounts and other clients are new transactions
+        let deposit = create_transaction(Deposit, 1, 3, Some(dec!(5)));
+        engine.process_transaction(deposit).unwrap();
+        let deposit = create_transaction(Deposit, 2, 4, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+        assert_eq!(
+            engine.clients[&ClientId::new(1)].available_balance,
+            dec!(15)
+        );
+
+        // Retries of a rejected transaction get its rejection reason
+        let withdrawal = create_transaction(Withdrawal, 1, 5, Some(dec!(100)));
+        let rejected = engine.process_transaction(withdrawal);
+        assert!(matches!(rejected, Err(InsufficientFunds { .. })));
+        let withdrawal = create_transaction(Withdrawal, 1, 6, Some(dec!(100)));
+        assert_eq!(engine.process_transaction(withdrawal), rejected);
+
+        // IDs are still unique across clients
+        let deposit = create_transaction(Deposit, 2, 1, Some(Decimal::ONE));
+        assert!(matches!(
+            engine.process_transaction(deposit),
+            Err(TransactionIdConflict { .. })
+        ));
+
+        // Rolled back transactions don't make retries of later ones
+        engine.begin_batch();
+        let deposit = create_transaction(Deposit, 1, 7, Some(dec!(7)));
+        engine.process_transaction(deposit).unwrap();
+        engine.rollback().unwrap();
+        let deposit = create_transaction(Deposit, 1, 8, Some(dec!(7)));
+        engine.process_transaction(deposit).unwrap();
+        assert_eq!(
+            engine.clients[&ClientId::new(1)].available_balance,
+            dec!(22)
+        );
+
+        // The content is rebuilt from the events
+        let mut replayed = engine.replay().unwrap();
+        let deposit = create_transaction(Deposit, 1, 9, Some(dec!(7)));
+        replayed.process_transaction(deposit).unwrap();
+        assert_eq!(
+            replayed.clients[&ClientId::new(1)].available_balance,
+            dec!(22)
+        );
+    }
+
+    #[test]
+    fn test_per_client_id_namespace() {
+        let mut engine = PaymentsEngine::new().with_id_namespace(IdNamespace::PerClient);
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(dec!(20))),
+            create_transaction(Deposit, 2, 1, Some(dec!(5))),
+            create_transaction(Dispute, 2, 1, None),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+
+        let account1 = &engine.clients[&ClientId::new(1)];
+        assert_eq!(account1.available_balance, dec!(20));
+        let account2 = &engine.clients[&ClientId::new(2)];
+        assert_eq!(account2.held_balance, dec!(5));
+
+        // The same ID is still a duplicate within a client
+        let deposit = create_transaction(Deposit, 2, 1, Some(dec!(6)));
+        let result = engine.process_transaction(deposit);
+        assert!(matches!(result, Err(IdempotencyConflict { .. })));
+
+        let dispute = engine.transaction(ClientId::new(2), TransactionId::new(1));
+        assert_eq!(dispute.unwrap().tx_status, TransactionStatus::Disputed);
+        let replayed = engine.replay().unwrap();
+        assert_eq!(replayed.client_accounts(), engine.client_accounts());
+    }
+
+    #[test]
+    fn test_deposit_to_locked_account() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+        engine.lock_account(ClientId::new(1));
+
+        let withdrawal = create_transaction(Deposit, 1, 2, Some(dec!(5)));
+        let result = engine.process_transaction(withdrawal);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::AccountLocked {
+                client: ClientId::new(1)
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::TEN);
+    }
+
+    #[test]
+    fn test_withdrawal_happy_path() {
+        let mut engine = PaymentsEngine::new();
+
+        // Setup: deposit first
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(5)));
+        let result = engine.process_transaction(withdrawal);
+
+        assert!(result.is_ok());
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(5));
+        assert_eq!(client_account.total(), dec!(5));
+    }
+
+    #[test]
+    fn test_withdrawal_exact_balance() {
+        let mut engine = PaymentsEngine::new();
+
+        // Setup: deposit
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(Decimal::TEN));
+        let result = engine.process_transaction(withdrawal);
+
+        assert!(result.is_ok());
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert_eq!(client_account.total(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_withdrawal_insufficient_funds() {
+        let mut engine = PaymentsEngine::new();
+
+        // Setup: deposit small amount
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::ONE));
+        engine.process_transaction(deposit).unwrap();
+
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(Decimal::TEN));
+        let result = engine.process_transaction(withdrawal);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::InsufficientFunds {
+                client: ClientId::new(1),
+                tx: TransactionId::new(2),
+                requested: Decimal::TEN,
+                available: Decimal::ONE
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ONE);
+    }
+
+    #[test]
+    fn test_withdrawal_missing_amount() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+
+        let withdrawal = create_transaction(Withdrawal, 1, 2, None);
+        let result = engine.process_transaction(withdrawal);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::MissingAmount {
+                client: ClientId::new(1),
+                tx: TransactionId::new(2)
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::TEN);
+    }
+
+    #[test]
+    fn test_withdrawal_from_locked_account() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+        engine.lock_account(ClientId::new(1));
+
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(5)));
+        let result = engine.process_transaction(withdrawal);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::AccountLocked {
+                client: ClientId::new(1)
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::TEN);
+    }
+
+    #[test]
+    fn test_withdrawal_duplicate_transaction_id_ignored() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(20)));
+        engine.process_transaction(deposit).unwrap();
+
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(5)));
+        engine.process_transaction(withdrawal.clone()).unwrap();
+
+        let result = engine.process_transaction(withdrawal);
+
+        assert!(result.is_ok());
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(15));
+    }
+
+    #[test]
+    fn test_withdrawal_from_zero_balance() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+        let withdrawal1 = create_transaction(Withdrawal, 1, 2, Some(Decimal::TEN));
+        engine.process_transaction(withdrawal1).unwrap();
+
+        let withdrawal2 = create_transaction(Withdrawal, 1, 3, Some(Decimal::ONE));
+        let result = engine.process_transaction(withdrawal2);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::InsufficientFunds {
+                client: ClientId::new(1),
+                tx: TransactionId::new(3),
+                requested: Decimal::ONE,
+                available: Decimal::ZERO
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_dispute_valid_deposit_happy_path() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+
+        let dispute = create_transaction(Dispute, 1, 1, None);
+        let result = engine.process_transaction(dispute);
+
+        assert!(result.is_ok());
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert_eq!(client_account.held_balance, Decimal::TEN);
+        assert_eq!(client_account.total(), Decimal::TEN);
+
+        let original_tx = engine
+            .transaction(ClientId::new(1), TransactionId::new(1))
+            .unwrap();
+        assert!(matches!(original_tx.tx_status, TransactionStatus::Disputed));
+    }
+
+    #[test]
+    fn test_dispute_transaction_not_found() {
+        let mut engine = PaymentsEngine::new();
+
+        let dispute = create_transaction(Dispute, 1, 1, None);
+        let result = engine.process_transaction(dispute);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::TransactionNotFound {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1)
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_dispute_rejected_transaction() {
+        let mut engine = PaymentsEngine::new();
+
+        // Rounds to zero with the default precision
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(0.00001)));
+        let error = engine.process_transaction(deposit).unwrap_err();
+        let rejected = TransactionStatus::Rejected(Box::new(error));
+
+        let dispute = create_transaction(Dispute, 1, 1, None);
+        let result = engine.process_transaction(dispute);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::InvalidDispute {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1),
+                tx_type: Deposit,
+                tx_status: rejected.clone(),
+            })
+        );
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Transaction 1 of client 1 cannot be disputed, as it is a rejected deposit"
+        );
+        let original_tx = engine.transaction(ClientId::new(1), TransactionId::new(1));
+        assert_eq!(original_tx.unwrap().tx_status, rejected);
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_dispute_wrong_client() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+
+        let dispute = create_transaction(Dispute, 2, 1, None);
+        let result = engine.process_transaction(dispute);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::ClientMismatch {
+                client: ClientId::new(2),
+                tx: TransactionId::new(1),
+                owner: ClientId::new(1)
+            })
+        );
+
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_dispute_withdrawal_invalid() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(20)));
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(withdrawal).unwrap();
+
+        let dispute = create_transaction(Dispute, 1, 2, None);
+        let result = engine.process_transaction(dispute);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::InvalidDispute {
+                client: ClientId::new(1),
+                tx: TransactionId::new(2),
+                tx_type: Withdrawal,
+                tx_status: TransactionStatus::Settled
+            })
+        );
+
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_dispute_already_disputed_transaction() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let dispute1 = create_transaction(Dispute, 1, 1, None);
+        engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(dispute1).unwrap();
+
+        let dispute2 = create_transaction(Dispute, 1, 1, None);
+        let result = engine.process_transaction(dispute2);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::InvalidDispute {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1),
+                tx_type: Deposit,
+                tx_status: TransactionStatus::Disputed
+            })
+        );
+
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert_eq!(client_account.held_balance, Decimal::TEN);
+    }
+
+    #[test]
+    fn test_dispute_resolved_transaction() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let dispute = create_transaction(Dispute, 1, 1, None);
+        let resolve = create_transaction(Resolve, 1, 1, None);
+        engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(dispute).unwrap();
+        engine.process_transaction(resolve).unwrap();
+
+        let dispute2 = create_transaction(Dispute, 1, 1, None);
+        let result = engine.process_transaction(dispute2);
+
+        assert!(result.is_ok());
+
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert_eq!(client_account.held_balance, Decimal::TEN);
+    }
+
+    #[test]
+    fn test_dispute_chargedback_transaction() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let dispute = create_transaction(Dispute, 1, 1, None);
+        let chargeback = create_transaction(Chargeback, 1, 1, None);
+        engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(dispute).unwrap();
+        engine.process_transaction(chargeback).unwrap();
+
+        let dispute2 = create_transaction(Dispute, 1, 1, None);
+        let result = engine.process_transaction(dispute2);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::AccountLocked {
+                client: ClientId::new(1)
+            })
+        );
+    }
+
+    #[test]
+    fn test_dispute_insufficient_funds_for_dispute() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(8)));
+        engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(withdrawal).unwrap();
+
+        let dispute = create_transaction(Dispute, 1, 1, None);
+        let result = engine.process_transaction(dispute);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::InsufficientFunds {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1),
+                requested: Decimal::TEN,
+                available: dec!(2)
+            })
+        );
+
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(2));
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_dispute_from_locked_account() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+        engine.lock_account(ClientId::new(1));
+
+        let dispute = create_transaction(Dispute, 1, 1, None);
+        let result = engine.process_transaction(dispute);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::AccountLocked {
+                client: ClientId::new(1)
+            })
+        );
+
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_resolve_happy_path() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let dispute = create_transaction(Dispute, 1, 1, None);
+        let resolve = create_transaction(Resolve, 1, 1, None);
+        engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(dispute).unwrap();
+        let result = engine.process_transaction(resolve);
+
+        assert!(result.is_ok());
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.total(), Decimal::TEN);
+    }
+
+    #[test]
+    fn test_resolve_undisputed_transaction() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let resolve = create_transaction(Resolve, 1, 1, None);
+        engine.process_transaction(deposit).unwrap();
+        let result = engine.process_transaction(resolve);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::InvalidTransactionStatus {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1),
+                expected: TransactionStatus::Disputed,
+                actual: TransactionStatus::Settled
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.total(), Decimal::TEN);
+    }
+
+    #[test]
+    fn test_resolve_transaction_not_found() {
+        let mut engine = PaymentsEngine::new();
+
+        let resolve = create_transaction(Resolve, 1, 1, None);
+        let result = engine.process_transaction(resolve);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::TransactionNotFound {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1)
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert_eq!(client_account.total(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_resolve_invalid_client() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let dispute = create_transaction(Dispute, 1, 1, None);
+        let resolve = create_transaction(Resolve, 2, 1, None);
+        engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(dispute).unwrap();
+        let result = engine.process_transaction(resolve);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::ClientMismatch {
+                client: ClientId::new(2),
+                tx: TransactionId::new(1),
+                owner: ClientId::new(1)
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert_eq!(client_account.held_balance, Decimal::TEN);
+        assert_eq!(client_account.total(), Decimal::TEN);
+    }
+
+    #[test]
+    fn test_chargeback_happy_path() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let dispute = create_transaction(Dispute, 1, 1, None);
+        let resolve = create_transaction(Chargeback, 1, 1, None);
+        engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(dispute).unwrap();
+        let result = engine.process_transaction(resolve);
+
+        assert!(result.is_ok());
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert_eq!(client_account.total(), Decimal::ZERO);
+        assert!(client_account.locked);
+    }
+
+    #[test]
+    fn test_chargeback_undisputed_transaction() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let resolve = create_transaction(Chargeback, 1, 1, None);
+        engine.process_transaction(deposit).unwrap();
+        let result = engine.process_transaction(resolve);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::InvalidTransactionStatus {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1),
+                expected: TransactionStatus::Disputed,
+                actual: TransactionStatus::Settled
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.total(), Decimal::TEN);
+        assert!(!client_account.locked);
+    }
+
+    #[test]
+    fn test_chargeback_transaction_not_found() {
+        let mut engine = PaymentsEngine::new();
+
+        let resolve = create_transaction(Chargeback, 1, 1, None);
+        let result = engine.process_transaction(resolve);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::TransactionNotFound {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1)
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert_eq!(client_account.total(), Decimal::ZERO);
+        assert!(!client_account.locked);
+    }
+
+    #[test]
+    fn test_chargeback_invalid_client() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let dispute = create_transaction(Dispute, 1, 1, None);
+        let resolve = create_transaction(Chargeback, 2, 1, None);
+        engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(dispute).unwrap();
+        let result = engine.process_transaction(resolve);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::ClientMismatch {
+                client: ClientId::new(2),
+                tx: TransactionId::new(1),
+                owner: ClientId::new(1)
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert_eq!(client_account.held_balance, Decimal::TEN);
+        assert_eq!(client_account.total(), Decimal::TEN);
+        assert!(!client_account.locked);
+    }
+
+    #[test]
+    fn test_processing_error_display() {
+        let error = ProcessingError::InsufficientFunds {
+            client: ClientId::new(1),
+            tx: TransactionId::new(2),
+            requested: Decimal::TEN,
+            available: Decimal::ONE,
+        };
+        assert_eq!(
+            error.to_string(),
+            "Transaction 2 of client 1 requires 10 but only 1 is available"
+        );
+        assert_eq!(error.client(), ClientId::new(1));
+
+        let error = ProcessingError::InvalidTransactionStatus {
+            client: ClientId::new(3),
+            tx: TransactionId::new(4),
+            expected: TransactionStatus::Disputed,
+            actual: TransactionStatus::ChargedBack,
+        };
+        assert_eq!(
+            error.to_string(),
+            "Transaction 4 of client 3 is charged back, expected it to be disputed"
+        );
+    }
+
+    #[test]
+    fn test_client_mismatch_counted_per_client() {
+        let mut engine = PaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+
+        for tx_type in [Dispute, Resolve, Chargeback] {
+            let result = engine.process_transaction(create_transaction(tx_type, 2, 1, None));
+            assert!(matches!(
+                result,
+                Err(ProcessingError::ClientMismatch { .. })
+            ));
+        }
+        let result = engine.process_transaction(create_transaction(Dispute, 3, 2, None));
+        assert!(matches!(
+            result,
+            Err(ProcessingError::TransactionNotFound { .. })
+        ));
+
+        assert_eq!(engine.client_mismatch_count(ClientId::new(1)), 0);
+        assert_eq!(engine.client_mismatch_count(ClientId::new(2)), 3);
+        assert_eq!(engine.client_mismatch_count(ClientId::new(3)), 0);
+
+        // Without a policy, the account is left untouched
+        let client_account = engine.clients.get(&ClientId::new(2)).unwrap();
+        assert!(!client_account.locked);
+        assert!(!client_account.flagged);
+    }
+
+    #[test]
+    fn test_client_mismatch_policy_flag() {
+        let mut engine = PaymentsEngine::new().with_client_mismatch_policy(ClientMismatchPolicy {
+            threshold: 2,
+            action: ClientMismatchAction::Flag,
+        });
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
 
-        // Safe to unwrap as client is guaranteed to exist at this point
-        let client = self.clients.get_mut(&transaction.client).unwrap();
+        let _ = engine.process_transaction(create_transaction(Dispute, 2, 1, None));
+        assert!(!engine.clients.get(&ClientId::new(2)).unwrap().flagged);
 
-        let original_amount = original_tx.amount.ok_or(MissingAmount)?.value();
-        client.available_balance = client
-            .available_balance
-            .checked_add(original_amount)
-            .ok_or(BalanceOverflow)?;
-        client.held_balance -= original_amount;
-        original_tx.tx_status = Resolved;
+        let _ = engine.process_transaction(create_transaction(Dispute, 2, 1, None));
+        let client_account = engine.clients.get(&ClientId::new(2)).unwrap();
+        assert!(client_account.flagged);
+        assert!(!client_account.locked);
 
-        Ok(())
+        let deposit = create_transaction(Deposit, 2, 2, Some(Decimal::ONE));
+        assert!(engine.process_transaction(deposit).is_ok());
     }
 
-    fn process_chargeback(&mut self, transaction: Transaction) -> Result<(), ProcessingError> {
-        let original_tx = self
-            .transaction_history
-            .get_mut(&transaction.tx)
-            .ok_or(TransactionNotFound)?;
-
-        if original_tx.client != transaction.client {
-            return Err(TransactionNotFound);
-        }
+    #[test]
+    fn test_client_mismatch_policy_lock() {
+        let mut engine = PaymentsEngine::new().with_client_mismatch_policy(ClientMismatchPolicy {
+            threshold: 1,
+            action: ClientMismatchAction::Lock,
+        });
 
-        if !matches!(original_tx.tx_status, Disputed) {
-            return Err(InvalidTransactionStatus);
-        }
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
 
-        // Safe to unwrap as client is guaranteed to exist at this point
-        let client = self.clients.get_mut(&transaction.client).unwrap();
+        let _ = engine.process_transaction(create_transaction(Dispute, 2, 1, None));
+        assert!(engine.clients.get(&ClientId::new(2)).unwrap().locked);
 
-        let original_amount = original_tx.amount.ok_or(MissingAmount)?.value();
-        client.held_balance -= original_amount;
-        client.locked = true;
-        original_tx.tx_status = ChargedBack;
+        let deposit = create_transaction(Deposit, 2, 2, Some(Decimal::ONE));
+        assert_eq!(
+            engine.process_transaction(deposit),
+            Err(ProcessingError::AccountLocked {
+                client: ClientId::new(2)
+            })
+        );
 
-        Ok(())
+        // The victim's account is not affected
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert!(!client_account.locked);
+        assert_eq!(client_account.available_balance, Decimal::TEN);
     }
 
-    pub fn client_accounts(&self) -> &HashMap<ClientId, ClientAccount> {
-        &self.clients
+    fn create_engine_with_fees() -> PaymentsEngine {
+        PaymentsEngine::new().with_fee_schedule(FeeSchedule {
+            deposit: None,
+            withdrawal: Some(FeeRule {
+                flat: dec!(0.5),
+                percentage: dec!(0.01),
+                min: None,
+                max: Some(dec!(2)),
+            }),
+            chargeback: Some(FeeRule::flat(dec!(15))),
+        })
     }
 
-    #[cfg(test)]
-    pub fn lock_account(&mut self, client_id: ClientId) {
-        if let Some(account) = self.clients.get_mut(&client_id) {
-            account.locked = true;
-        }
-    }
-}
+    #[test]
+    fn test_withdrawal_fee_booked() {
+        let mut engine = create_engine_with_fees();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::domain::{Amount, TransactionStatus};
-    use rust_decimal::dec;
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(100)));
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(50)));
+        engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(withdrawal).unwrap();
 
-    fn create_transaction(
-        tx_type: TransactionType,
-        client: u16,
-        tx_id: u32,
-        amount: Option<Decimal>,
-    ) -> Transaction {
-        Transaction {
-            tx_type,
-            client: ClientId::new(client),
-            tx: TransactionId::new(tx_id),
-            amount: amount.map(|a| Amount::new(a).unwrap()),
-            tx_status: TransactionStatus::Pending,
-        }
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(49));
+        assert_eq!(client_account.fees, Decimal::ONE);
+        assert_eq!(engine.house_fees(), Decimal::ONE);
+        assert_eq!(
+            engine.fee_entries(),
+            &[FeeEntry {
+                client: ClientId::new(1),
+                tx: TransactionId::new(2),
+                kind: FeeKind::Withdrawal,
+                amount: Decimal::ONE,
+            }]
+        );
     }
 
     #[test]
-    fn test_payments_engine_new() {
-        let engine = PaymentsEngine::new();
-        assert!(engine.clients.is_empty());
-        assert!(engine.transaction_history.is_empty());
-        assert!(engine.client_accounts().is_empty());
+    fn test_deposit_fee_booked() {
+        let mut engine = PaymentsEngine::new().with_fee_schedule(FeeSchedule {
+            deposit: Some(FeeRule::flat(dec!(2))),
+            ..FeeSchedule::default()
+        });
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(100)));
+        engine.process_transaction(deposit).unwrap();
+        // The fee can't exceed the deposited amount
+        let deposit = create_transaction(Deposit, 2, 2, Some(Decimal::ONE));
+        engine.process_transaction(deposit).unwrap();
+
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(98));
+        assert_eq!(client_account.fees, dec!(2));
+        let client_account = engine.clients.get(&ClientId::new(2)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert_eq!(client_account.fees, Decimal::ONE);
+        assert_eq!(engine.house_fees(), dec!(3));
+        assert_eq!(engine.fee_entries()[0].kind, FeeKind::Deposit);
+        assert!(engine.journal().is_balanced());
     }
 
     #[test]
-    fn test_client_account_total() {
-        let mut account = ClientAccount::default();
-        assert_eq!(account.total(), Decimal::ZERO);
+    fn test_withdrawal_fee_insufficient_funds() {
+        let mut engine = create_engine_with_fees();
 
-        account.available_balance = Decimal::TEN;
-        account.held_balance = dec!(5);
-        assert_eq!(account.total(), dec!(15));
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+        let result = engine.process_transaction(withdrawal);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::InsufficientFunds {
+                client: ClientId::new(1),
+                tx: TransactionId::new(2),
+                requested: dec!(10.6),
+                available: Decimal::TEN,
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.fees, Decimal::ZERO);
+        assert!(engine.fee_entries().is_empty());
     }
 
     #[test]
-    fn test_client_account_default() {
-        let account = ClientAccount::default();
-        assert_eq!(account.available_balance, Decimal::ZERO);
-        assert_eq!(account.held_balance, Decimal::ZERO);
-        assert!(!account.locked);
+    fn test_chargeback_fee_limited_to_available_balance() {
+        let mut engine = create_engine_with_fees();
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(dec!(100))),
+            create_transaction(Deposit, 1, 2, Some(Decimal::TEN)),
+            create_transaction(Dispute, 1, 2, None),
+            create_transaction(Chargeback, 1, 2, None),
+            create_transaction(Deposit, 2, 3, Some(Decimal::TEN)),
+            create_transaction(Deposit, 2, 4, Some(Decimal::ONE)),
+            create_transaction(Dispute, 2, 3, None),
+            create_transaction(Chargeback, 2, 3, None),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(85));
+        assert_eq!(client_account.fees, dec!(15));
+        let client_account = engine.clients.get(&ClientId::new(2)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert_eq!(client_account.fees, Decimal::ONE);
+
+        assert_eq!(engine.house_fees(), dec!(16));
+        assert_eq!(engine.client_fee_entries(ClientId::new(2)).count(), 1);
     }
 
     #[test]
-    fn test_deposit_happy_path() {
+    fn test_no_fees_without_schedule() {
         let mut engine = PaymentsEngine::new();
-        let transaction = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
 
-        let result = engine.process_transaction(transaction);
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(withdrawal).unwrap();
+
+        assert_eq!(engine.house_fees(), Decimal::ZERO);
+        assert!(engine.fee_entries().is_empty());
+    }
+
+    #[test]
+    fn test_withdrawal_single_limit_exceeded() {
+        let mut engine = PaymentsEngine::new().with_limits(LimitConfig {
+            default: Limits {
+                max_withdrawal: Some(dec!(5)),
+                ..Limits::default()
+            },
+            per_client: HashMap::from([(ClientId::new(2), Limits::default())]),
+        });
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Deposit, 2, 2, Some(Decimal::TEN)),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+
+        let withdrawal = create_transaction(Withdrawal, 1, 3, Some(dec!(6)));
+        let result = engine.process_transaction(withdrawal);
+        assert_eq!(
+            result,
+            Err(ProcessingError::LimitExceeded {
+                client: ClientId::new(1),
+                tx: TransactionId::new(3),
+                limit: LimitKind::SingleWithdrawal { max: dec!(5) },
+            })
+        );
+
+        // Client 2 has its own limits, which replace the default ones
+        let withdrawal = create_transaction(Withdrawal, 2, 4, Some(dec!(6)));
+        assert!(engine.process_transaction(withdrawal).is_ok());
 
-        assert!(result.is_ok());
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
         assert_eq!(client_account.available_balance, Decimal::TEN);
-        assert_eq!(client_account.held_balance, Decimal::ZERO);
-        assert_eq!(client_account.total(), Decimal::TEN);
-        assert!(!client_account.locked);
     }
 
     #[test]
-    fn test_deposit_multiple_deposits_same_client() {
-        let mut engine = PaymentsEngine::new();
-        let tx1 = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        let tx2 = create_transaction(Deposit, 1, 2, Some(dec!(5)));
+    fn test_withdrawal_daily_limit_exceeded() {
+        let clock = ManualClock::new(0);
+        let mut engine = PaymentsEngine::new()
+            .with_clock(clock.clone())
+            .with_limits(LimitConfig {
+                default: Limits {
+                    daily_withdrawal_total: Some(Decimal::TEN),
+                    ..Limits::default()
+                },
+                per_client: HashMap::new(),
+            });
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(100)));
+        let withdrawal1 = create_transaction(Withdrawal, 1, 2, Some(dec!(8)));
+        engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(withdrawal1).unwrap();
 
-        engine.process_transaction(tx1).unwrap();
-        engine.process_transaction(tx2).unwrap();
+        // Rejected withdrawals don't count towards the limit
+        clock.advance(60);
+        let withdrawal2 = create_transaction(Withdrawal, 1, 3, Some(dec!(3)));
+        assert_eq!(
+            engine.process_transaction(withdrawal2),
+            Err(ProcessingError::LimitExceeded {
+                client: ClientId::new(1),
+                tx: TransactionId::new(3),
+                limit: LimitKind::DailyWithdrawalTotal {
+                    max: Decimal::TEN,
+                    withdrawn: dec!(8),
+                },
+            })
+        );
+        let withdrawal3 = create_transaction(Withdrawal, 1, 4, Some(dec!(2)));
+        engine.process_transaction(withdrawal3).unwrap();
+
+        clock.advance(24 * 60 * 60);
+        let withdrawal4 = create_transaction(Withdrawal, 1, 5, Some(dec!(8)));
+        engine.process_transaction(withdrawal4).unwrap();
 
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, dec!(15));
-        assert_eq!(client_account.total(), dec!(15));
+        assert_eq!(client_account.available_balance, dec!(82));
     }
 
     #[test]
-    fn test_deposit_missing_amount() {
-        let mut engine = PaymentsEngine::new();
-        let transaction = create_transaction(Deposit, 1, 1, None);
+    fn test_deposit_velocity_limit_exceeded() {
+        let clock = ManualClock::new(1_000);
+        let mut engine = PaymentsEngine::new()
+            .with_clock(clock.clone())
+            .with_limits(LimitConfig {
+                default: Limits {
+                    max_deposits: Some(2),
+                    deposit_window: 60,
+                    ..Limits::default()
+                },
+                per_client: HashMap::new(),
+            });
+
+        engine
+            .process_transaction(create_transaction(Deposit, 1, 1, Some(Decimal::ONE)))
+            .unwrap();
+        engine
+            .process_transaction(create_transaction(Deposit, 1, 2, Some(Decimal::ONE)))
+            .unwrap();
+        let result =
+            engine.process_transaction(create_transaction(Deposit, 1, 3, Some(Decimal::ONE)));
+        assert!(matches!(
+            result,
+            Err(ProcessingError::LimitExceeded {
+                limit: LimitKind::DepositVelocity { .. },
+                ..
+            })
+        ));
+
+        // Other clients are not affected
+        engine
+            .process_transaction(create_transaction(Deposit, 2, 4, Some(Decimal::ONE)))
+            .unwrap();
 
-        let result = engine.process_transaction(transaction);
+        clock.advance(60);
+        // Retrying the rejected deposit returns its original outcome, a new one goes through
+        let retry =
+            engine.process_transaction(create_transaction(Deposit, 1, 3, Some(Decimal::ONE)));
+        assert_eq!(retry, result);
+        engine
+            .process_transaction(create_transaction(Deposit, 1, 5, Some(Decimal::ONE)))
+            .unwrap();
 
-        assert_eq!(result, Err(ProcessingError::MissingAmount));
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(3));
+    }
+
+    #[test]
+    fn test_risk_rule_deny_recorded_in_audit_trail() {
+        let mut engine = PaymentsEngine::new()
+            .with_risk_rule(LargeAmountRule {
+                threshold: dec!(5),
+                action: RiskAction::Flag,
+            })
+            .with_risk_rule(DenyListRule {
+                clients: HashSet::from([ClientId::new(2)]),
+            });
+
+        let deposit = create_transaction(Deposit, 2, 1, Some(Decimal::TEN));
+        let result = engine.process_transaction(deposit);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::RiskRejected {
+                client: ClientId::new(2),
+                tx: TransactionId::new(1),
+                rule: "deny_list".to_string(),
+                reason: "client 2 is deny-listed".to_string(),
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(2)).unwrap();
         assert_eq!(client_account.available_balance, Decimal::ZERO);
-        assert_eq!(client_account.held_balance, Decimal::ZERO);
+
+        let rules: Vec<&str> = engine
+            .audit_trail()
+            .iter()
+            .map(|entry| entry.rule.as_str())
+            .collect();
+        assert_eq!(rules, vec!["large_amount", "deny_list"]);
     }
 
     #[test]
-    fn test_deposit_new_client_creation() {
-        let mut engine = PaymentsEngine::new();
-        let transaction = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+    fn test_risk_rule_flag_lets_transaction_through() {
+        let mut engine = PaymentsEngine::new().with_risk_rule(LargeAmountRule {
+            threshold: dec!(5),
+            action: RiskAction::Flag,
+        });
 
-        engine.process_transaction(transaction).unwrap();
+        let small = create_transaction(Deposit, 1, 1, Some(dec!(5)));
+        engine.process_transaction(small).unwrap();
+        assert!(!engine.clients.get(&ClientId::new(1)).unwrap().flagged);
+        assert!(engine.audit_trail().is_empty());
+
+        let large = create_transaction(Deposit, 1, 2, Some(Decimal::TEN));
+        engine.process_transaction(large).unwrap();
 
-        assert_eq!(engine.clients.len(), 1);
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::TEN);
-        assert_eq!(client_account.held_balance, Decimal::ZERO);
-        assert!(!client_account.locked);
+        assert!(client_account.flagged);
+        assert_eq!(client_account.available_balance, dec!(15));
+        assert_eq!(
+            engine.audit_trail(),
+            &[AuditEntry {
+                client: ClientId::new(1),
+                tx: TransactionId::new(2),
+                rule: "large_amount".to_string(),
+                outcome: RiskOutcome::Flag {
+                    reason: "amount 10 is above 5".to_string()
+                },
+            }]
+        );
     }
 
     #[test]
-    fn test_deposit_decimal_precision() {
-        let mut engine = PaymentsEngine::new();
-        let amount = dec!(12.3456);
-        let transaction = create_transaction(Deposit, 1, 1, Some(amount));
+    fn test_risk_rule_rapid_withdrawal() {
+        let clock = ManualClock::new(0);
+        let mut engine = PaymentsEngine::new()
+            .with_clock(clock.clone())
+            .with_risk_rule(RapidWithdrawalRule {
+                window: 60,
+                action: RiskAction::Deny,
+            });
 
-        engine.process_transaction(transaction).unwrap();
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
 
-        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, amount);
+        clock.advance(30);
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(Decimal::ONE));
+        assert!(matches!(
+            engine.process_transaction(withdrawal.clone()),
+            Err(ProcessingError::RiskRejected { .. })
+        ));
+
+        clock.advance(30);
+        let withdrawal = create_transaction(Withdrawal, 1, 3, Some(Decimal::ONE));
+        assert!(engine.process_transaction(withdrawal).is_ok());
     }
 
     #[test]
-    fn test_deposit_large_amount() {
-        let mut engine = PaymentsEngine::new();
-        let large_amount = Decimal::MAX;
-        let transaction = create_transaction(Deposit, 1, 1, Some(large_amount));
+    fn test_risk_rule_dispute_count() {
+        let mut engine = PaymentsEngine::new().with_risk_rule(DisputeCountRule {
+            max_disputes: 1,
+            action: RiskAction::Deny,
+        });
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Dispute, 1, 1, None),
+            create_transaction(Resolve, 1, 1, None),
+            create_transaction(Deposit, 1, 2, Some(Decimal::ONE)),
+            create_transaction(Dispute, 1, 1, None),
+            create_transaction(Resolve, 1, 1, None),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
-        let result = engine.process_transaction(transaction);
+        let deposit = create_transaction(Deposit, 1, 3, Some(Decimal::ONE));
+        assert!(matches!(
+            engine.process_transaction(deposit),
+            Err(ProcessingError::RiskRejected { .. })
+        ));
+    }
 
-        assert!(result.is_ok());
-        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, large_amount);
+    fn create_engine_with_overdraft(overdraft: Decimal) -> PaymentsEngine {
+        PaymentsEngine::new().with_limits(LimitConfig {
+            default: Limits::default(),
+            per_client: HashMap::from([(
+                ClientId::new(1),
+                Limits {
+                    overdraft: Some(overdraft),
+                    ..Limits::default()
+                },
+            )]),
+        })
     }
 
     #[test]
-    fn test_deposit_balance_overflow() {
-        let mut engine = PaymentsEngine::new();
-        let large_amount = Decimal::MAX;
-        let tx1 = create_transaction(Deposit, 1, 1, Some(large_amount));
-        let tx2 = create_transaction(Deposit, 1, 2, Some(dec!(1)));
+    fn test_withdrawal_within_overdraft() {
+        let mut engine = create_engine_with_overdraft(Decimal::TEN);
 
-        let result1 = engine.process_transaction(tx1);
-        let result2 = engine.process_transaction(tx2);
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(5)));
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(15)));
+        engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(withdrawal).unwrap();
 
-        assert!(result1.is_ok());
-        assert_eq!(result2, Err(BalanceOverflow));
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, large_amount);
+        assert_eq!(client_account.available_balance, -Decimal::TEN);
+        assert_eq!(client_account.overdraft_used(), Decimal::TEN);
+        assert_eq!(client_account.total(), -Decimal::TEN);
+
+        let withdrawal = create_transaction(Withdrawal, 1, 3, Some(dec!(0.01)));
+        assert_eq!(
+            engine.process_transaction(withdrawal),
+            Err(ProcessingError::InsufficientFunds {
+                client: ClientId::new(1),
+                tx: TransactionId::new(3),
+                requested: dec!(0.01),
+                available: Decimal::ZERO,
+            })
+        );
+
+        // Clients without a credit line can't go below zero
+        let withdrawal = create_transaction(Withdrawal, 2, 4, Some(dec!(0.01)));
+        assert!(matches!(
+            engine.process_transaction(withdrawal),
+            Err(ProcessingError::InsufficientFunds { .. })
+        ));
     }
 
     #[test]
-    fn test_deposit_duplicate_deposit_ignored() {
-        let mut engine = PaymentsEngine::new();
-        let tx1 = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+    fn test_deposit_repays_overdraft() {
+        let mut engine = create_engine_with_overdraft(Decimal::TEN);
 
-        engine.process_transaction(tx1.clone()).unwrap();
-        let result = engine.process_transaction(tx1);
+        let withdrawal = create_transaction(Withdrawal, 1, 1, Some(dec!(4)));
+        let deposit = create_transaction(Deposit, 1, 2, Some(dec!(5)));
+        engine.process_transaction(withdrawal).unwrap();
+        engine.process_transaction(deposit).unwrap();
 
-        assert!(result.is_ok());
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.available_balance, Decimal::ONE);
+        assert_eq!(client_account.overdraft_used(), Decimal::ZERO);
     }
 
     #[test]
-    fn test_deposit_to_locked_account() {
-        let mut engine = PaymentsEngine::new();
+    fn test_dispute_cannot_deepen_overdraft() {
+        let mut engine = create_engine_with_overdraft(Decimal::TEN);
 
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(5)));
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(7)));
         engine.process_transaction(deposit).unwrap();
-        engine.lock_account(ClientId::new(1));
+        engine.process_transaction(withdrawal).unwrap();
 
-        let withdrawal = create_transaction(Deposit, 1, 2, Some(dec!(5)));
-        let result = engine.process_transaction(withdrawal);
+        let dispute = create_transaction(Dispute, 1, 1, None);
+        assert_eq!(
+            engine.process_transaction(dispute),
+            Err(ProcessingError::InsufficientFunds {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1),
+                requested: dec!(5),
+                available: dec!(-2),
+            })
+        );
 
-        assert_eq!(result, Err(ProcessingError::AccountLocked));
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.available_balance, dec!(-2));
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
     }
 
     #[test]
-    fn test_withdrawal_happy_path() {
+    fn test_dispute_after_withdrawal_refused_by_default() {
         let mut engine = PaymentsEngine::new();
 
-        // Setup: deposit first
         let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(8)));
         engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(withdrawal).unwrap();
 
-        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(5)));
-        let result = engine.process_transaction(withdrawal);
+        let dispute = create_transaction(Dispute, 1, 1, None);
+        assert!(matches!(
+            engine.process_transaction(dispute),
+            Err(ProcessingError::InsufficientFunds { .. })
+        ));
+        assert_eq!(engine.collections().count(), 0);
+    }
+
+    #[test]
+    fn test_chargeback_after_withdrawal_creates_receivable() {
+        let mut engine = PaymentsEngine::new()
+            .with_negative_balance_policy(NegativeBalancePolicy::AllowNegative)
+            .with_fee_schedule(FeeSchedule {
+                chargeback: Some(FeeRule::flat(dec!(15))),
+                ..FeeSchedule::default()
+            });
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Withdrawal, 1, 2, Some(dec!(8))),
+            create_transaction(Dispute, 1, 1, None),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
-        assert!(result.is_ok());
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, dec!(5));
-        assert_eq!(client_account.total(), dec!(5));
+        assert_eq!(client_account.available_balance, dec!(-8));
+        assert_eq!(client_account.held_balance, Decimal::TEN);
+        assert_eq!(client_account.total(), dec!(2));
+        assert_eq!(client_account.receivable(), Decimal::ZERO);
+
+        let chargeback = create_transaction(Chargeback, 1, 1, None);
+        engine.process_transaction(chargeback).unwrap();
+
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(-8));
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+        assert!(client_account.locked);
+        assert_eq!(client_account.receivable(), dec!(8));
+        // No chargeback fee can be collected from an account in the red
+        assert_eq!(client_account.fees, Decimal::ZERO);
+
+        let collections: Vec<_> = engine.collections().map(|(client, _)| *client).collect();
+        assert_eq!(collections, vec![ClientId::new(1)]);
     }
 
     #[test]
-    fn test_withdrawal_exact_balance() {
+    fn test_resolve_after_withdrawal_restores_balance() {
+        let mut engine = PaymentsEngine::new()
+            .with_negative_balance_policy(NegativeBalancePolicy::AllowNegative);
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Withdrawal, 1, 2, Some(dec!(8))),
+            create_transaction(Dispute, 1, 1, None),
+            create_transaction(Resolve, 1, 1, None),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(2));
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+        assert!(!client_account.locked);
+        assert_eq!(engine.collections().count(), 0);
+    }
+
+    #[test]
+    fn test_authorize_and_capture() {
         let mut engine = PaymentsEngine::new();
 
-        // Setup: deposit
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        engine.process_transaction(deposit).unwrap();
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Authorize, 1, 2, Some(dec!(6))),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
-        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(Decimal::TEN));
-        let result = engine.process_transaction(withdrawal);
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(4));
+        assert_eq!(client_account.held_balance, dec!(6));
+        assert_eq!(
+            engine
+                .transaction(ClientId::new(1), TransactionId::new(2))
+                .unwrap()
+                .tx_status,
+            Authorized
+        );
+
+        // Partial capture settles part of the hold and releases the rest
+        let capture = create_transaction(Capture, 1, 2, Some(dec!(4.5)));
+        engine.process_transaction(capture).unwrap();
 
-        assert!(result.is_ok());
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::ZERO);
-        assert_eq!(client_account.total(), Decimal::ZERO);
+        assert_eq!(client_account.available_balance, dec!(5.5));
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+        assert_eq!(client_account.total(), dec!(5.5));
+        assert_eq!(
+            engine
+                .transaction(ClientId::new(1), TransactionId::new(2))
+                .unwrap()
+                .tx_status,
+            Captured
+        );
+
+        // A capture is final
+        let capture = create_transaction(Capture, 1, 2, None);
+        assert_eq!(
+            engine.process_transaction(capture),
+            Err(ProcessingError::InvalidTransactionStatus {
+                client: ClientId::new(1),
+                tx: TransactionId::new(2),
+                expected: Authorized,
+                actual: Captured,
+            })
+        );
     }
 
     #[test]
-    fn test_withdrawal_insufficient_funds() {
+    fn test_capture_without_amount_settles_full_hold() {
         let mut engine = PaymentsEngine::new();
 
-        // Setup: deposit small amount
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::ONE));
-        engine.process_transaction(deposit).unwrap();
-
-        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(Decimal::TEN));
-        let result = engine.process_transaction(withdrawal);
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Authorize, 1, 2, Some(dec!(6))),
+            create_transaction(Capture, 1, 2, None),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
-        assert_eq!(result, Err(ProcessingError::InsufficientFunds));
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::ONE);
+        assert_eq!(client_account.available_balance, dec!(4));
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
     }
 
     #[test]
-    fn test_withdrawal_missing_amount() {
+    fn test_capture_more_than_authorized() {
         let mut engine = PaymentsEngine::new();
 
         let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let authorize = create_transaction(Authorize, 1, 2, Some(dec!(6)));
         engine.process_transaction(deposit).unwrap();
+        engine.process_transaction(authorize).unwrap();
+
+        let capture = create_transaction(Capture, 1, 2, Some(dec!(7)));
+        assert_eq!(
+            engine.process_transaction(capture),
+            Err(ProcessingError::InsufficientFunds {
+                client: ClientId::new(1),
+                tx: TransactionId::new(2),
+                requested: dec!(7),
+                available: dec!(6),
+            })
+        );
 
-        let withdrawal = create_transaction(Withdrawal, 1, 2, None);
-        let result = engine.process_transaction(withdrawal);
-
-        assert_eq!(result, Err(ProcessingError::MissingAmount));
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.held_balance, dec!(6));
     }
 
     #[test]
-    fn test_withdrawal_from_locked_account() {
+    fn test_authorize_insufficient_funds() {
         let mut engine = PaymentsEngine::new();
 
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::ONE));
+        let authorize = create_transaction(Authorize, 1, 2, Some(dec!(2)));
         engine.process_transaction(deposit).unwrap();
-        engine.lock_account(ClientId::new(1));
+        assert!(matches!(
+            engine.process_transaction(authorize),
+            Err(ProcessingError::InsufficientFunds { .. })
+        ));
+    }
 
-        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(5)));
-        let result = engine.process_transaction(withdrawal);
+    #[test]
+    fn test_void_releases_hold() {
+        let mut engine = PaymentsEngine::new();
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Authorize, 1, 2, Some(dec!(6))),
+            create_transaction(Void, 1, 2, None),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
-        assert_eq!(result, Err(ProcessingError::AccountLocked));
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
         assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+        assert_eq!(
+            engine
+                .transaction(ClientId::new(1), TransactionId::new(2))
+                .unwrap()
+                .tx_status,
+            Voided
+        );
+
+        // Only holds of the same client can be voided, and only authorizations are holds
+        let void = create_transaction(Void, 2, 2, None);
+        assert!(matches!(
+            engine.process_transaction(void),
+            Err(ProcessingError::ClientMismatch { .. })
+        ));
+        let void = create_transaction(Void, 1, 1, None);
+        assert!(matches!(
+            engine.process_transaction(void),
+            Err(ProcessingError::InvalidTransactionStatus { .. })
+        ));
     }
 
     #[test]
-    fn test_withdrawal_duplicate_transaction_id_ignored() {
-        let mut engine = PaymentsEngine::new();
-
-        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(20)));
-        engine.process_transaction(deposit).unwrap();
+    fn test_holds_expire() {
+        let clock = ManualClock::new(0);
+        let mut engine = PaymentsEngine::new()
+            .with_clock(clock.clone())
+            .with_hold_expiry(60);
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Authorize, 1, 2, Some(dec!(3))),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+        clock.advance(30);
+        let authorize = create_transaction(Authorize, 1, 3, Some(dec!(2)));
+        engine.process_transaction(authorize).unwrap();
+
+        // The first hold expires as soon as the client sends another transaction
+        clock.advance(30);
+        let capture = create_transaction(Capture, 1, 2, None);
+        assert_eq!(
+            engine.process_transaction(capture),
+            Err(ProcessingError::InvalidTransactionStatus {
+                client: ClientId::new(1),
+                tx: TransactionId::new(2),
+                expected: Authorized,
+                actual: Expired,
+            })
+        );
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(8));
+        assert_eq!(client_account.held_balance, dec!(2));
 
-        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(5)));
-        engine.process_transaction(withdrawal.clone()).unwrap();
+        // And the other one when all holds are expired
+        clock.advance(30);
+        engine.expire_holds();
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+    }
 
-        let result = engine.process_transaction(withdrawal);
+    /// Engine in which client 2 can overdraw by one, so that its withdrawal leaves room in the
+    /// clearing account for client 1 to hold more than [`Decimal::MAX`] in total.
+    fn create_engine_with_clearing_room() -> (PaymentsEngine, Transaction) {
+        let engine = PaymentsEngine::new().with_limits(LimitConfig {
+            default: Limits::default(),
+            per_client: HashMap::from([(
+                ClientId::new(2),
+                Limits {
+                    overdraft: Some(Decimal::ONE),
+                    ..Limits::default()
+                },
+            )]),
+        });
+        let withdrawal = create_transaction(Withdrawal, 2, 10, Some(Decimal::ONE));
+
+        (engine, withdrawal)
+    }
 
-        assert!(result.is_ok());
+    #[test]
+    fn test_void_balance_overflow() {
+        let (mut engine, withdrawal) = create_engine_with_clearing_room();
+
+        let transactions = vec![
+            withdrawal,
+            create_transaction(Deposit, 1, 1, Some(Decimal::MAX)),
+            create_transaction(Authorize, 1, 2, Some(Decimal::ONE)),
+            create_transaction(Deposit, 1, 3, Some(Decimal::ONE)),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+
+        let void = create_transaction(Void, 1, 2, None);
+        assert_eq!(
+            engine.process_transaction(void),
+            Err(ProcessingError::BalanceOverflow {
+                client: ClientId::new(1),
+                tx: TransactionId::new(2),
+            })
+        );
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, dec!(15));
+        assert_eq!(client_account.available_balance, Decimal::MAX);
+        assert_eq!(client_account.held_balance, Decimal::ONE);
+        assert_eq!(
+            engine
+                .transaction(ClientId::new(1), TransactionId::new(2))
+                .unwrap()
+                .tx_status,
+            Authorized
+        );
     }
 
     #[test]
-    fn test_withdrawal_from_zero_balance() {
-        let mut engine = PaymentsEngine::new();
-
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        engine.process_transaction(deposit).unwrap();
-        let withdrawal1 = create_transaction(Withdrawal, 1, 2, Some(Decimal::TEN));
-        engine.process_transaction(withdrawal1).unwrap();
+    fn test_hold_expiry_balance_overflow() {
+        let clock = ManualClock::new(0);
+        let (engine, withdrawal) = create_engine_with_clearing_room();
+        let mut engine = engine.with_clock(clock.clone()).with_hold_expiry(60);
+
+        let transactions = vec![
+            withdrawal,
+            create_transaction(Deposit, 1, 1, Some(Decimal::MAX)),
+            create_transaction(Authorize, 1, 2, Some(Decimal::ONE)),
+            create_transaction(Deposit, 1, 3, Some(Decimal::ONE)),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
-        let withdrawal2 = create_transaction(Withdrawal, 1, 3, Some(Decimal::ONE));
-        let result = engine.process_transaction(withdrawal2);
+        // The hold stays open while releasing it would overflow
+        clock.advance(60);
+        engine.expire_holds();
+        let withdrawal = create_transaction(Withdrawal, 1, 4, Some(Decimal::ONE));
+        engine.process_transaction(withdrawal).unwrap();
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(
+            client_account.available_balance,
+            Decimal::MAX - Decimal::ONE
+        );
+        assert_eq!(client_account.held_balance, Decimal::ONE);
 
-        assert_eq!(result, Err(ProcessingError::InsufficientFunds));
+        engine.expire_holds();
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::ZERO);
+        assert_eq!(client_account.available_balance, Decimal::MAX);
+        assert_eq!(client_account.held_balance, Decimal::ZERO);
+        assert_eq!(
+            engine
+                .transaction(ClientId::new(1), TransactionId::new(2))
+                .unwrap()
+                .tx_status,
+            Expired
+        );
     }
 
     #[test]
-    fn test_dispute_valid_deposit_happy_path() {
-        let mut engine = PaymentsEngine::new();
+    fn test_authorize_counts_towards_withdrawal_limits() {
+        let mut engine = PaymentsEngine::new()
+            .with_clock(ManualClock::new(0))
+            .with_limits(LimitConfig {
+                default: Limits {
+                    daily_withdrawal_total: Some(Decimal::TEN),
+                    ..Limits::default()
+                },
+                per_client: HashMap::new(),
+            });
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(dec!(100))),
+            create_transaction(Authorize, 1, 2, Some(dec!(8))),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        engine.process_transaction(deposit).unwrap();
+        let withdrawal = create_transaction(Withdrawal, 1, 3, Some(dec!(3)));
+        assert!(matches!(
+            engine.process_transaction(withdrawal),
+            Err(ProcessingError::LimitExceeded { .. })
+        ));
+    }
 
-        let dispute = create_transaction(Dispute, 1, 1, None);
-        let result = engine.process_transaction(dispute);
+    #[test]
+    fn test_partial_refunds() {
+        let mut engine = PaymentsEngine::new();
 
-        assert!(result.is_ok());
-        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::ZERO);
-        assert_eq!(client_account.held_balance, Decimal::TEN);
-        assert_eq!(client_account.total(), Decimal::TEN);
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Refund, 1, 1, Some(dec!(3))),
+            create_transaction(Refund, 1, 1, Some(dec!(4))),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(3));
         let original_tx = engine
-            .transaction_history
-            .get(&TransactionId::new(1))
+            .transaction(ClientId::new(1), TransactionId::new(1))
             .unwrap();
-        assert!(matches!(original_tx.tx_status, TransactionStatus::Disputed));
+        assert_eq!(original_tx.refunded, dec!(7));
+        assert_eq!(original_tx.tx_status, Settled);
+
+        let refund = create_transaction(Refund, 1, 1, Some(dec!(3.01)));
+        assert_eq!(
+            engine.process_transaction(refund),
+            Err(ProcessingError::RefundTooLarge {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1),
+                requested: dec!(3.01),
+                refundable: dec!(3),
+            })
+        );
+
+        let refund = create_transaction(Refund, 1, 1, Some(dec!(3)));
+        engine.process_transaction(refund).unwrap();
+        let original_tx = engine
+            .transaction(ClientId::new(1), TransactionId::new(1))
+            .unwrap();
+        assert_eq!(original_tx.tx_status, Refunded);
+        assert_eq!(
+            engine.clients[&ClientId::new(1)].available_balance,
+            Decimal::ZERO
+        );
     }
 
     #[test]
-    fn test_dispute_transaction_not_found() {
+    fn test_refund_requires_deposit() {
         let mut engine = PaymentsEngine::new();
 
-        let dispute = create_transaction(Dispute, 1, 1, None);
-        let result = engine.process_transaction(dispute);
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Withdrawal, 1, 2, Some(Decimal::ONE)),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
-        assert_eq!(result, Err(ProcessingError::TransactionNotFound));
-        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::ZERO);
-        assert_eq!(client_account.held_balance, Decimal::ZERO);
+        let refund = create_transaction(Refund, 1, 2, Some(Decimal::ONE));
+        assert_eq!(
+            engine.process_transaction(refund),
+            Err(ProcessingError::InvalidRefund {
+                client: ClientId::new(1),
+                tx: TransactionId::new(2),
+                tx_type: Withdrawal,
+                tx_status: Settled,
+            })
+        );
+
+        let refund = create_transaction(Refund, 1, 3, Some(Decimal::ONE));
+        assert!(matches!(
+            engine.process_transaction(refund),
+            Err(ProcessingError::TransactionNotFound { .. })
+        ));
+        let refund = create_transaction(Refund, 2, 1, Some(Decimal::ONE));
+        assert!(matches!(
+            engine.process_transaction(refund),
+            Err(ProcessingError::ClientMismatch { .. })
+        ));
     }
 
     #[test]
-    fn test_dispute_wrong_client() {
+    fn test_dispute_after_partial_refund() {
         let mut engine = PaymentsEngine::new();
 
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        engine.process_transaction(deposit).unwrap();
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Deposit, 1, 2, Some(dec!(5))),
+            create_transaction(Refund, 1, 1, Some(dec!(4))),
+            create_transaction(Dispute, 1, 1, None),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
-        let dispute = create_transaction(Dispute, 2, 1, None);
-        let result = engine.process_transaction(dispute);
+        // Only the unrefunded part of the deposit is held
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(5));
+        assert_eq!(client_account.held_balance, dec!(6));
 
-        assert_eq!(result, Err(ProcessingError::TransactionNotFound));
+        // A disputed deposit can't be refunded
+        let refund = create_transaction(Refund, 1, 1, Some(Decimal::ONE));
+        assert!(matches!(
+            engine.process_transaction(refund),
+            Err(ProcessingError::InvalidRefund { .. })
+        ));
 
+        let chargeback = create_transaction(Chargeback, 1, 1, None);
+        engine.process_transaction(chargeback).unwrap();
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::TEN);
+        assert_eq!(client_account.available_balance, dec!(5));
         assert_eq!(client_account.held_balance, Decimal::ZERO);
+        assert_eq!(client_account.total(), dec!(5));
     }
 
     #[test]
-    fn test_dispute_withdrawal_invalid() {
+    fn test_dispute_after_full_refund() {
         let mut engine = PaymentsEngine::new();
 
-        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(20)));
-        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(Decimal::TEN));
-        engine.process_transaction(deposit).unwrap();
-        engine.process_transaction(withdrawal).unwrap();
-
-        let dispute = create_transaction(Dispute, 1, 2, None);
-        let result = engine.process_transaction(dispute);
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Refund, 1, 1, Some(Decimal::TEN)),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
-        assert_eq!(result, Err(ProcessingError::InvalidDispute));
+        let dispute = create_transaction(Dispute, 1, 1, None);
+        assert_eq!(
+            engine.process_transaction(dispute),
+            Err(ProcessingError::InvalidDispute {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1),
+                tx_type: Deposit,
+                tx_status: Refunded,
+            })
+        );
+    }
 
-        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::TEN);
-        assert_eq!(client_account.held_balance, Decimal::ZERO);
+    fn january_2024() -> InterestPeriod {
+        InterestPeriod {
+            start: Date::new(2024, 1, 1).unwrap(),
+            end: Date::new(2024, 2, 1).unwrap(),
+        }
     }
 
     #[test]
-    fn test_dispute_already_disputed_transaction() {
-        let mut engine = PaymentsEngine::new();
-
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        let dispute1 = create_transaction(Dispute, 1, 1, None);
-        engine.process_transaction(deposit).unwrap();
-        engine.process_transaction(dispute1).unwrap();
+    fn test_accrue_interest() {
+        let mut engine = PaymentsEngine::new().with_interest(InterestConfig {
+            default_rate: dec!(0.05),
+            per_client: HashMap::from([(ClientId::new(2), Decimal::ZERO)]),
+            day_count: DayCount::Actual360,
+            ..InterestConfig::default()
+        });
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(dec!(1000))),
+            create_transaction(Deposit, 1, 2, Some(dec!(500))),
+            create_transaction(Dispute, 1, 2, None),
+            create_transaction(Deposit, 2, 3, Some(dec!(1000))),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
-        let dispute2 = create_transaction(Dispute, 1, 1, None);
-        let result = engine.process_transaction(dispute2);
+        let postings = engine.accrue_interest(january_2024()).to_vec();
+        assert_eq!(
+            postings,
+            vec![InterestEntry {
+                client: ClientId::new(1),
+                tx: TransactionId::new(u32::MAX),
+                start: Date::new(2024, 1, 1).unwrap(),
+                end: Date::new(2024, 2, 1).unwrap(),
+                amount: dec!(4.3056),
+            }]
+        );
 
-        assert_eq!(result, Err(ProcessingError::InvalidDispute));
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(1004.3056));
+        assert_eq!(client_account.held_balance, dec!(500));
+        let interest_tx = engine
+            .system_transaction(TransactionId::new(u32::MAX))
+            .unwrap();
+        assert_eq!(interest_tx.tx_type, Interest);
+        assert_eq!(interest_tx.amount.unwrap().value(), dec!(4.3056));
 
+        // Accruing the same period again doesn't post anything
+        assert!(engine.accrue_interest(january_2024()).is_empty());
+        assert_eq!(engine.interest_entries().len(), 1);
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::ZERO);
-        assert_eq!(client_account.held_balance, Decimal::TEN);
+        assert_eq!(client_account.available_balance, dec!(1004.3056));
     }
 
     #[test]
-    fn test_dispute_resolved_transaction() {
-        let mut engine = PaymentsEngine::new();
+    fn test_accrue_interest_system_transactions() {
+        let mut engine = PaymentsEngine::new().with_interest(InterestConfig {
+            default_rate: dec!(0.05),
+            ..InterestConfig::default()
+        });
+
+        let transactions = vec![
+            // Uses the first ID the engine generates, which doesn't collide with it
+            create_transaction(Deposit, 1, u32::MAX, Some(dec!(1000))),
+            create_transaction(Deposit, 2, 2, Some(dec!(1000))),
+            create_transaction(Deposit, 3, 3, Some(dec!(1000))),
+            create_transaction(Deposit, 3, 4, Some(dec!(500))),
+            create_transaction(Dispute, 3, 4, None),
+            create_transaction(Chargeback, 3, 4, None),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        let dispute = create_transaction(Dispute, 1, 1, None);
-        let resolve = create_transaction(Resolve, 1, 1, None);
+        // Locked accounts don't earn interest
+        let postings = engine.accrue_interest(january_2024()).to_vec();
+        let posted: Vec<(ClientId, TransactionId)> = postings
+            .iter()
+            .map(|posting| (posting.client, posting.tx))
+            .collect();
+        assert_eq!(
+            posted,
+            vec![
+                (ClientId::new(1), TransactionId::new(u32::MAX)),
+                (ClientId::new(2), TransactionId::new(u32::MAX - 1)),
+            ]
+        );
+        let interest_tx = engine.system_transaction(TransactionId::new(u32::MAX));
+        assert_eq!(interest_tx.unwrap().client, ClientId::new(1));
+        let deposit = engine.transaction(ClientId::new(1), TransactionId::new(u32::MAX));
+        assert_eq!(deposit.unwrap().tx_type, Deposit);
+
+        // Interest transactions can't be referenced or submitted by the input
+        let dispute = create_transaction(Dispute, 2, u32::MAX - 1, None);
+        assert!(matches!(
+            engine.process_transaction(dispute),
+            Err(ProcessingError::TransactionNotFound { .. })
+        ));
+        let interest = create_transaction(Interest, 2, 5, Some(Decimal::ONE));
+        assert_eq!(
+            engine.process_transaction(interest),
+            Err(ProcessingError::SystemTransaction {
+                client: ClientId::new(2),
+                tx: TransactionId::new(5),
+                tx_type: Interest,
+            })
+        );
+        // Nor do their IDs conflict with the ones of the input
+        let deposit = create_transaction(Deposit, 2, u32::MAX - 1, Some(Decimal::ONE));
         engine.process_transaction(deposit).unwrap();
-        engine.process_transaction(dispute).unwrap();
-        engine.process_transaction(resolve).unwrap();
+        assert_eq!(
+            engine.clients[&ClientId::new(2)].available_balance,
+            dec!(1001) + postings[1].amount
+        );
+    }
 
-        let dispute2 = create_transaction(Dispute, 1, 1, None);
-        let result = engine.process_transaction(dispute2);
+    #[test]
+    fn test_accrue_interest_including_held() {
+        let mut engine = PaymentsEngine::new().with_interest(InterestConfig {
+            default_rate: dec!(0.05),
+            day_count: DayCount::Actual360,
+            include_held: true,
+            ..InterestConfig::default()
+        });
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(dec!(600))),
+            create_transaction(Deposit, 1, 2, Some(dec!(400))),
+            create_transaction(Dispute, 1, 2, None),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
-        assert!(result.is_ok());
+        engine.accrue_interest(january_2024());
+        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
+        assert_eq!(client_account.available_balance, dec!(604.3056));
+        assert_eq!(client_account.held_balance, dec!(400));
+    }
+
+    #[test]
+    fn test_journal_projects_client_accounts() {
+        let mut engine = PaymentsEngine::new()
+            .with_fee_schedule(FeeSchedule {
+                deposit: None,
+                withdrawal: Some(FeeRule::flat(dec!(0.5))),
+                chargeback: Some(FeeRule::flat(Decimal::ONE)),
+            })
+            .with_interest(InterestConfig {
+                default_rate: dec!(0.05),
+                ..InterestConfig::default()
+            });
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(dec!(100))),
+            create_transaction(Deposit, 1, 2, Some(dec!(20))),
+            create_transaction(Withdrawal, 1, 3, Some(dec!(30))),
+            create_transaction(Dispute, 1, 2, None),
+            create_transaction(Resolve, 1, 2, None),
+            create_transaction(Refund, 1, 2, Some(dec!(5))),
+            create_transaction(Authorize, 1, 4, Some(dec!(10))),
+            create_transaction(Capture, 1, 4, Some(dec!(7))),
+            create_transaction(Deposit, 2, 5, Some(dec!(50))),
+            create_transaction(Dispute, 2, 5, None),
+            create_transaction(Chargeback, 2, 5, None),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+        engine.accrue_interest(january_2024());
+
+        let journal = engine.journal();
+        assert!(journal.is_balanced());
+        assert_eq!(journal.trial_balance(), Some(Decimal::ZERO));
+        for (client_id, account) in engine.client_accounts() {
+            assert_eq!(
+                journal.balance(LedgerAccount::ClientAvailable(*client_id)),
+                account.available_balance
+            );
+            assert_eq!(
+                journal.balance(LedgerAccount::ClientHeld(*client_id)),
+                account.held_balance
+            );
+        }
 
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::ZERO);
-        assert_eq!(client_account.held_balance, Decimal::TEN);
+        assert_eq!(client_account.available_balance, dec!(77.8291));
+        assert_eq!(engine.house_fees(), dec!(0.5));
+        assert_eq!(journal.balance(LedgerAccount::HouseClearing), dec!(-78));
+        assert_eq!(journal.balance(LedgerAccount::HouseInterest), dec!(-0.3291));
     }
 
     #[test]
-    fn test_dispute_chargedback_transaction() {
+    fn test_rejected_transactions_post_nothing() {
         let mut engine = PaymentsEngine::new();
 
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        let dispute = create_transaction(Dispute, 1, 1, None);
-        let chargeback = create_transaction(Chargeback, 1, 1, None);
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::ONE));
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(Decimal::TEN));
         engine.process_transaction(deposit).unwrap();
-        engine.process_transaction(dispute).unwrap();
-        engine.process_transaction(chargeback).unwrap();
-
-        let dispute2 = create_transaction(Dispute, 1, 1, None);
-        let result = engine.process_transaction(dispute2);
+        assert!(engine.process_transaction(withdrawal).is_err());
 
-        assert_eq!(result, Err(ProcessingError::AccountLocked));
+        let entries = engine.journal().entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].kind, EntryKind::Deposit);
+        assert_eq!(entries[0].tx, Some(TransactionId::new(1)));
     }
 
     #[test]
-    fn test_dispute_insufficient_funds_for_dispute() {
+    fn test_state_at() {
         let mut engine = PaymentsEngine::new();
 
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(8)));
-        engine.process_transaction(deposit).unwrap();
-        engine.process_transaction(withdrawal).unwrap();
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Deposit, 1, 2, Some(dec!(5))),
+            create_transaction(Withdrawal, 1, 3, Some(dec!(3))),
+            create_transaction(Dispute, 1, 2, None),
+            create_transaction(Withdrawal, 1, 4, Some(dec!(100))),
+            create_transaction(Deposit, 2, 5, Some(Decimal::ONE)),
+        ];
+        for tx in transactions {
+            let _ = engine.process_transaction(tx);
+        }
+        assert_eq!(engine.sequence(), 6);
+
+        let balances_at = |sequence| {
+            engine
+                .state_at(sequence)
+                .unwrap()
+                .client_accounts()
+                .get(&ClientId::new(1))
+                .map(|account| (account.available_balance, account.held_balance))
+        };
+        assert_eq!(balances_at(0), None);
+        assert_eq!(balances_at(1), Some((Decimal::TEN, Decimal::ZERO)));
+        assert_eq!(balances_at(2), Some((dec!(15), Decimal::ZERO)));
+        assert_eq!(balances_at(3), Some((dec!(12), Decimal::ZERO)));
+        assert_eq!(balances_at(4), Some((dec!(7), dec!(5))));
+        // The rejected withdrawal didn't change anything
+        assert_eq!(balances_at(5), Some((dec!(7), dec!(5))));
+
+        assert_eq!(engine.state_at(5).unwrap().client_accounts().len(), 1);
+        assert_eq!(
+            engine.replay().unwrap().client_accounts(),
+            engine.client_accounts()
+        );
+    }
 
-        let dispute = create_transaction(Dispute, 1, 1, None);
-        let result = engine.process_transaction(dispute);
+    #[test]
+    fn test_replay_failure() {
+        let mut engine = PaymentsEngine::new();
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::ONE));
+        engine.process_transaction(deposit).unwrap();
+        // An event log that doesn't match the engine, as the client never had these funds
+        engine.events.push(EventRecord {
+            sequence: 2,
+            event: Event::TransactionApplied {
+                transaction: create_transaction(Withdrawal, 1, 2, Some(Decimal::TEN)),
+                at: 0,
+            },
+        });
+
+        assert!(engine.state_at(1).is_ok());
+        let Err(error) = engine.state_at(2) else {
+            panic!("replay should fail");
+        };
+        assert_eq!(error.sequence, 2);
+        assert!(matches!(
+            error.error,
+            ProcessingError::InsufficientFunds { .. }
+        ));
+    }
 
-        assert_eq!(result, Err(ProcessingError::InsufficientFunds));
+    #[test]
+    fn test_replay_rebuilds_engine() {
+        let clock = ManualClock::new(0);
+        let mut engine = PaymentsEngine::new()
+            .with_clock(clock.clone())
+            .with_hold_expiry(60)
+            .with_client_mismatch_policy(ClientMismatchPolicy {
+                threshold: 1,
+                action: ClientMismatchAction::Lock,
+            })
+            .with_fee_schedule(FeeSchedule {
+                withdrawal: Some(FeeRule::flat(dec!(0.1))),
+                ..FeeSchedule::default()
+            })
+            .with_interest(InterestConfig {
+                default_rate: dec!(0.05),
+                ..InterestConfig::default()
+            })
+            .with_risk_rule(LargeAmountRule {
+                threshold: dec!(50),
+                action: RiskAction::Flag,
+            });
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(dec!(100))),
+            create_transaction(Authorize, 1, 2, Some(dec!(20))),
+            create_transaction(Withdrawal, 1, 3, Some(dec!(5))),
+            create_transaction(Deposit, 2, 4, Some(dec!(10))),
+            create_transaction(Dispute, 2, 1, None),
+        ];
+        for tx in transactions {
+            let _ = engine.process_transaction(tx);
+        }
+        clock.advance(120);
+        let withdrawal = create_transaction(Withdrawal, 1, 5, Some(Decimal::ONE));
+        engine.process_transaction(withdrawal).unwrap();
+        engine.accrue_interest(january_2024());
 
         let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, dec!(2));
+        assert!(client_account.flagged);
         assert_eq!(client_account.held_balance, Decimal::ZERO);
+        assert!(engine.clients.get(&ClientId::new(2)).unwrap().locked);
+
+        let replayed = engine.replay().unwrap();
+        assert_eq!(replayed.client_accounts(), engine.client_accounts());
+        assert_eq!(replayed.events().len(), engine.events().len());
+        assert_eq!(replayed.fee_entries(), engine.fee_entries());
+        assert_eq!(replayed.interest_entries(), engine.interest_entries());
+        assert_eq!(
+            replayed.client_mismatch_count(ClientId::new(2)),
+            engine.client_mismatch_count(ClientId::new(2))
+        );
+        assert!(replayed.journal().is_balanced());
+
+        // Before the hold expired
+        let before_expiry = engine.state_at(5).unwrap();
+        let client_account = before_expiry.client_accounts().get(&ClientId::new(1));
+        assert_eq!(client_account.unwrap().held_balance, dec!(20));
     }
 
     #[test]
-    fn test_dispute_from_locked_account() {
+    fn test_precision_rounds_amounts() {
         let mut engine = PaymentsEngine::new();
 
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        engine.process_transaction(deposit).unwrap();
-        engine.lock_account(ClientId::new(1));
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(dec!(1.00005))),
+            create_transaction(Deposit, 1, 2, Some(dec!(0.12345))),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
 
-        let dispute = create_transaction(Dispute, 1, 1, None);
-        let result = engine.process_transaction(dispute);
+        assert_eq!(
+            engine.clients[&ClientId::new(1)].available_balance,
+            dec!(1.1234)
+        );
+        let deposit = engine
+            .transaction(ClientId::new(1), TransactionId::new(2))
+            .unwrap();
+        assert_eq!(deposit.amount.unwrap().value(), dec!(0.1234));
+    }
 
-        assert_eq!(result, Err(ProcessingError::AccountLocked));
+    #[test]
+    fn test_precision_rounding_mode() {
+        let mut engine = PaymentsEngine::new().with_precision(Precision {
+            decimal_places: 2,
+            rounding: Rounding::Truncate,
+            strict: false,
+        });
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(1.999)));
+        engine.process_transaction(deposit).unwrap();
 
-        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::TEN);
-        assert_eq!(client_account.held_balance, Decimal::ZERO);
+        assert_eq!(
+            engine.clients[&ClientId::new(1)].available_balance,
+            dec!(1.99)
+        );
     }
 
     #[test]
-    fn test_resolve_happy_path() {
-        let mut engine = PaymentsEngine::new();
-
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        let dispute = create_transaction(Dispute, 1, 1, None);
-        let resolve = create_transaction(Resolve, 1, 1, None);
+    fn test_strict_precision_rejects_amounts() {
+        let mut engine = PaymentsEngine::new().with_precision(Precision {
+            decimal_places: 2,
+            strict: true,
+            ..Precision::default()
+        });
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(1.50)));
         engine.process_transaction(deposit).unwrap();
-        engine.process_transaction(dispute).unwrap();
-        let result = engine.process_transaction(resolve);
 
-        assert!(result.is_ok());
-        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::TEN);
-        assert_eq!(client_account.total(), Decimal::TEN);
+        let deposit = create_transaction(Deposit, 1, 2, Some(dec!(1.005)));
+        let result = engine.process_transaction(deposit);
+        assert!(matches!(
+            result,
+            Err(PrecisionExceeded {
+                decimal_places: 2,
+                ..
+            })
+        ));
+        assert_eq!(
+            engine.clients[&ClientId::new(1)].available_balance,
+            dec!(1.5)
+        );
+        let rejected = engine
+            .transaction(ClientId::new(1), TransactionId::new(2))
+            .unwrap();
+        assert_eq!(
+            rejected.tx_status,
+            TransactionStatus::Rejected(Box::new(result.unwrap_err()))
+        );
+        assert_eq!(rejected.amount.unwrap().value(), dec!(1.00));
+        assert_eq!(rejected.submitted_amount.unwrap().value(), dec!(1.005));
     }
 
     #[test]
-    fn test_resolve_undisputed_transaction() {
-        let mut engine = PaymentsEngine::new();
-
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        let resolve = create_transaction(Resolve, 1, 1, None);
+    fn test_retry_compares_amounts_before_rounding() {
+        let precision = Precision {
+            decimal_places: 2,
+            ..Precision::default()
+        };
+        let mut engine = PaymentsEngine::new().with_precision(Precision {
+            strict: true,
+            ..precision
+        });
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(1.00)));
         engine.process_transaction(deposit).unwrap();
-        let result = engine.process_transaction(resolve);
-
-        assert_eq!(result, Err(ProcessingError::InvalidTransactionStatus));
-        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::TEN);
-        assert_eq!(client_account.total(), Decimal::TEN);
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(1.004)));
+        assert_eq!(
+            engine.process_transaction(deposit),
+            Err(IdempotencyConflict {
+                client: ClientId::new(1),
+                tx: TransactionId::new(1)
+            })
+        );
+
+        let mut engine = PaymentsEngine::new().with_precision(precision);
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(1.004)));
+        engine.process_transaction(deposit.clone()).unwrap();
+        engine.process_transaction(deposit).unwrap();
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(1.00)));
+        assert!(matches!(
+            engine.process_transaction(deposit),
+            Err(IdempotencyConflict { .. })
+        ));
+        assert_eq!(engine.clients[&ClientId::new(1)].available_balance, dec!(1));
     }
 
     #[test]
-    fn test_resolve_transaction_not_found() {
+    fn test_precision_rejects_amounts_rounding_to_zero() {
         let mut engine = PaymentsEngine::new();
 
-        let resolve = create_transaction(Resolve, 1, 1, None);
-        let result = engine.process_transaction(resolve);
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(0.00001)));
+        let result = engine.process_transaction(deposit);
+        assert!(matches!(result, Err(PrecisionExceeded { .. })));
+        assert_eq!(
+            engine.clients[&ClientId::new(1)].available_balance,
+            Decimal::ZERO
+        );
+        let rejected = engine
+            .transaction(ClientId::new(1), TransactionId::new(1))
+            .unwrap();
+        assert!(rejected.amount.is_none());
+    }
 
-        assert_eq!(result, Err(ProcessingError::TransactionNotFound));
-        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::ZERO);
-        assert_eq!(client_account.total(), Decimal::ZERO);
+    /// Balances, transactions and logs of the engine, to check that a rollback left no trace.
+    #[derive(Debug, PartialEq)]
+    struct EngineState {
+        clients: HashMap<ClientId, ClientAccount>,
+        transactions: Vec<(TransactionId, TransactionStatus)>,
+        journal: Vec<JournalEntry>,
+        fees: Vec<FeeEntry>,
+        events: usize,
+        sequence: SequenceNumber,
     }
 
-    #[test]
-    fn test_resolve_invalid_client() {
-        let mut engine = PaymentsEngine::new();
+    fn engine_state(engine: &PaymentsEngine) -> EngineState {
+        let mut transactions: Vec<_> = engine
+            .transaction_history
+            .values()
+            .map(|transaction| (transaction.tx, transaction.tx_status.clone()))
+            .collect();
+        transactions.sort_unstable_by_key(|(tx, _)| tx.value());
+
+        EngineState {
+            clients: engine.clients.clone(),
+            transactions,
+            journal: engine.journal.entries().to_vec(),
+            fees: engine.fee_entries().to_vec(),
+            events: engine.events().len(),
+            sequence: engine.sequence(),
+        }
+    }
 
+    #[test]
+    fn test_batch_rollback_leaves_no_trace() {
+        let mut engine = PaymentsEngine::new().with_fee_schedule(FeeSchedule {
+            deposit: None,
+            withdrawal: Some(FeeRule::flat(dec!(0.5))),
+            chargeback: None,
+        });
         let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        let dispute = create_transaction(Dispute, 1, 1, None);
-        let resolve = create_transaction(Resolve, 2, 1, None);
         engine.process_transaction(deposit).unwrap();
-        engine.process_transaction(dispute).unwrap();
-        let result = engine.process_transaction(resolve);
+        let before = engine_state(&engine);
+
+        engine.begin_batch();
+        let transactions = vec![
+            create_transaction(Deposit, 1, 2, Some(Decimal::ONE)),
+            create_transaction(Withdrawal, 1, 3, Some(dec!(2))),
+            create_transaction(Dispute, 1, 2, None),
+            create_transaction(Deposit, 2, 4, Some(Decimal::TEN)),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+        engine.rollback().unwrap();
 
-        assert_eq!(result, Err(TransactionNotFound));
-        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::ZERO);
-        assert_eq!(client_account.held_balance, Decimal::TEN);
-        assert_eq!(client_account.total(), Decimal::TEN);
+        assert_eq!(engine_state(&engine), before);
+        assert!(engine.journal().is_balanced());
+
+        // Transaction IDs of the rolled back batch can be used again
+        let deposit = create_transaction(Deposit, 2, 4, Some(Decimal::ONE));
+        engine.process_transaction(deposit).unwrap();
+        assert_eq!(
+            engine.clients[&ClientId::new(2)].available_balance,
+            Decimal::ONE
+        );
+        assert_eq!(
+            engine.replay().unwrap().client_accounts(),
+            engine.client_accounts()
+        );
     }
 
     #[test]
-    fn test_chargeback_happy_path() {
+    fn test_batch_commit() {
         let mut engine = PaymentsEngine::new();
 
+        engine.begin_batch();
         let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        let dispute = create_transaction(Dispute, 1, 1, None);
-        let resolve = create_transaction(Chargeback, 1, 1, None);
         engine.process_transaction(deposit).unwrap();
-        engine.process_transaction(dispute).unwrap();
-        let result = engine.process_transaction(resolve);
-
-        assert!(result.is_ok());
-        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::ZERO);
-        assert_eq!(client_account.total(), Decimal::ZERO);
-        assert!(client_account.locked);
+        engine.commit().unwrap();
+
+        assert_eq!(
+            engine.clients[&ClientId::new(1)].available_balance,
+            Decimal::TEN
+        );
+        assert_eq!(engine.commit(), Err(NoOpenBatch));
+        assert_eq!(engine.rollback(), Err(NoOpenBatch));
     }
 
     #[test]
-    fn test_chargeback_undisputed_transaction() {
+    fn test_nested_batches() {
         let mut engine = PaymentsEngine::new();
 
+        engine.begin_batch();
         let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        let resolve = create_transaction(Chargeback, 1, 1, None);
         engine.process_transaction(deposit).unwrap();
-        let result = engine.process_transaction(resolve);
 
-        assert_eq!(result, Err(ProcessingError::InvalidTransactionStatus));
-        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::TEN);
-        assert_eq!(client_account.total(), Decimal::TEN);
-        assert!(!client_account.locked);
-    }
+        engine.begin_batch();
+        let withdrawal = create_transaction(Withdrawal, 1, 2, Some(dec!(4)));
+        engine.process_transaction(withdrawal).unwrap();
+        engine.rollback().unwrap();
 
-    #[test]
-    fn test_chargeback_transaction_not_found() {
-        let mut engine = PaymentsEngine::new();
+        engine.begin_batch();
+        let deposit = create_transaction(Deposit, 1, 3, Some(Decimal::ONE));
+        engine.process_transaction(deposit).unwrap();
+        engine.commit().unwrap();
 
-        let resolve = create_transaction(Chargeback, 1, 1, None);
-        let result = engine.process_transaction(resolve);
+        assert_eq!(
+            engine.clients[&ClientId::new(1)].available_balance,
+            dec!(11)
+        );
+        engine.rollback().unwrap();
 
-        assert_eq!(result, Err(ProcessingError::TransactionNotFound));
-        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::ZERO);
-        assert_eq!(client_account.total(), Decimal::ZERO);
-        assert!(!client_account.locked);
+        // Rolling back the outer batch also undoes the committed inner one
+        assert!(engine.clients.is_empty());
+        assert!(engine.transaction_history.is_empty());
+        assert_eq!(engine.sequence(), 0);
     }
 
     #[test]
-    fn test_chargeback_invalid_client() {
-        let mut engine = PaymentsEngine::new();
+    fn test_batch_saves_only_what_it_changes() {
+        let mut engine = PaymentsEngine::new().with_interest(InterestConfig {
+            default_rate: dec!(0.05),
+            ..InterestConfig::default()
+        });
+        for client in 1..=100 {
+            let deposit = create_transaction(Deposit, client, client.into(), Some(Decimal::TEN));
+            engine.process_transaction(deposit).unwrap();
+        }
+        let before = engine_state(&engine);
+
+        engine.begin_batch();
+        let transactions = vec![
+            create_transaction(Withdrawal, 1, 101, Some(Decimal::ONE)),
+            create_transaction(Dispute, 2, 2, None),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+        let undo = &engine.batches[0].undo;
+        assert_eq!(undo.clients.len(), 2);
+        assert_eq!(undo.transactions.len(), 2);
+
+        // Interest changes every client but the one with its whole balance disputed
+        assert_eq!(engine.accrue_interest(january_2024()).len(), 99);
+        engine.rollback().unwrap();
+
+        assert_eq!(engine_state(&engine), before);
+        assert_eq!(engine.risk_profiles[&ClientId::new(2)].dispute_count, 0);
+        // The period can be accrued again
+        assert_eq!(engine.accrue_interest(january_2024()).len(), 100);
+    }
 
-        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
-        let dispute = create_transaction(Dispute, 1, 1, None);
-        let resolve = create_transaction(Chargeback, 2, 1, None);
-        engine.process_transaction(deposit).unwrap();
-        engine.process_transaction(dispute).unwrap();
-        let result = engine.process_transaction(resolve);
+    #[test]
+    fn test_process_batch() {
+        let mut engine = PaymentsEngine::new();
 
-        assert_eq!(result, Err(TransactionNotFound));
-        let client_account = engine.clients.get(&ClientId::new(1)).unwrap();
-        assert_eq!(client_account.available_balance, Decimal::ZERO);
-        assert_eq!(client_account.held_balance, Decimal::TEN);
-        assert_eq!(client_account.total(), Decimal::TEN);
-        assert!(!client_account.locked);
+        let transfer = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Withdrawal, 1, 2, Some(dec!(4))),
+        ];
+        engine.process_batch(transfer).unwrap();
+        let before = engine_state(&engine);
+
+        let transfer = vec![
+            create_transaction(Withdrawal, 1, 3, Some(dec!(5))),
+            create_transaction(Deposit, 2, 4, Some(dec!(5))),
+            create_transaction(Withdrawal, 1, 5, Some(dec!(5))),
+        ];
+        let rejected = engine.process_batch(transfer).unwrap_err();
+
+        assert_eq!(rejected.index, 2);
+        assert!(matches!(rejected.error, InsufficientFunds { .. }));
+        assert_eq!(engine_state(&engine), before);
     }
 }
@@ -0,0 +1,51 @@
+use crate::clock::Timestamp;
+use crate::domain::{ClientId, Transaction};
+use crate::engine::ProcessingError;
+use crate::interest::InterestPeriod;
+
+/// Number of the input row an event was recorded for, counting from 1 over every input given to the
+/// engine, malformed rows included. Events recorded outside of a row, e.g. interest accruals, carry
+/// the number of the last row.
+pub type SequenceNumber = u64;
+
+/// Something that changed the state of the engine. Folding every event in order rebuilds it.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// An account was created, even if the transaction that created it was then rejected
+    AccountOpened {
+        client: ClientId,
+    },
+    /// A transaction that was applied to the accounts, with the time it was processed at
+    TransactionApplied {
+        transaction: Transaction,
+        at: Timestamp,
+    },
+    /// A deposit, withdrawal or authorization that was rejected. It is kept in the history as
+    /// [`crate::domain::TransactionStatus::Rejected`], so that retries get the same outcome
+    TransactionRejected {
+        transaction: Transaction,
+        error: ProcessingError,
+    },
+    /// The client referenced another client's transaction, see [`crate::engine::ClientMismatchPolicy`]
+    ClientMismatch {
+        client: ClientId,
+    },
+    /// A risk rule flagged the account for review
+    AccountFlagged {
+        client: ClientId,
+    },
+    /// Holds of the client that were older than the hold expiry at that time were released
+    HoldsExpired {
+        client: ClientId,
+        at: Timestamp,
+    },
+    InterestAccrued {
+        period: InterestPeriod,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct EventRecord {
+    pub sequence: SequenceNumber,
+    pub event: Event,
+}
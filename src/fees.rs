@@ -0,0 +1,232 @@
+use crate::domain::{ClientId, TransactionId, serialize_decimal};
+use crate::precision::Precision;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+#[derive(Deserialize, Serialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FeeKind {
+    Deposit,
+    Withdrawal,
+    Chargeback,
+}
+
+/// Fee made of a flat part plus a percentage of the transaction amount, optionally bounded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeeRule {
+    pub flat: Decimal,
+    /// Fraction of the amount, e.g. `0.01` for 1%
+    pub percentage: Decimal,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+}
+
+impl FeeRule {
+    pub fn flat(fee: Decimal) -> Self {
+        Self {
+            flat: fee,
+            ..Self::default()
+        }
+    }
+
+    /// Computes the fee for the given amount, rounded to the precision of the engine.
+    pub fn fee_for(&self, amount: Decimal, precision: &Precision) -> Decimal {
+        let mut fee = self
+            .percentage
+            .checked_mul(amount)
+            .and_then(|percentage_fee| percentage_fee.checked_add(self.flat))
+            .unwrap_or(Decimal::MAX);
+
+        if let Some(min) = self.min {
+            fee = fee.max(min);
+        }
+        if let Some(max) = self.max {
+            fee = fee.min(max);
+        }
+
+        precision.round(fee.max(Decimal::ZERO))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeeSchedule {
+    pub deposit: Option<FeeRule>,
+    pub withdrawal: Option<FeeRule>,
+    pub chargeback: Option<FeeRule>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FeeScheduleRow {
+    kind: FeeKind,
+    flat: Option<Decimal>,
+    percentage: Option<Decimal>,
+    min: Option<Decimal>,
+    max: Option<Decimal>,
+}
+
+impl FeeSchedule {
+    pub fn fee_for(&self, kind: FeeKind, amount: Decimal, precision: &Precision) -> Decimal {
+        let rule = match kind {
+            FeeKind::Deposit => &self.deposit,
+            FeeKind::Withdrawal => &self.withdrawal,
+            FeeKind::Chargeback => &self.chargeback,
+        };
+
+        rule.as_ref()
+            .map(|rule| rule.fee_for(amount, precision))
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    /// Reads a fee schedule from a CSV with the `kind,flat,percentage,min,max` columns, where every
+    /// column but `kind` is optional. Later rows for the same kind override earlier ones.
+    pub fn from_csv(input: impl io::Read) -> Result<Self, csv::Error> {
+        let mut csv_reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(input);
+
+        let mut schedule = Self::default();
+        for result in csv_reader.deserialize::<FeeScheduleRow>() {
+            let row = result?;
+            let rule = Some(FeeRule {
+                flat: row.flat.unwrap_or_default(),
+                percentage: row.percentage.unwrap_or_default(),
+                min: row.min,
+                max: row.max,
+            });
+
+            match row.kind {
+                FeeKind::Deposit => schedule.deposit = rule,
+                FeeKind::Withdrawal => schedule.withdrawal = rule,
+                FeeKind::Chargeback => schedule.chargeback = rule,
+            }
+        }
+
+        Ok(schedule)
+    }
+}
+
+/// A fee charged to a client, booked separately from the transaction it was charged for.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FeeEntry {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub kind: FeeKind,
+    /// Written with its own scale, see [`Precision::round_padded`]
+    #[serde(serialize_with = "serialize_decimal")]
+    pub amount: Decimal,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::precision::Rounding;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_fee_rule_flat_and_percentage() {
+        let rule = FeeRule {
+            flat: dec!(0.5),
+            percentage: dec!(0.01),
+            min: None,
+            max: None,
+        };
+
+        assert_eq!(rule.fee_for(dec!(100), &Precision::default()), dec!(1.5));
+        assert_eq!(
+            rule.fee_for(Decimal::ZERO, &Precision::default()),
+            dec!(0.5)
+        );
+    }
+
+    #[test]
+    fn test_fee_rule_min_max() {
+        let rule = FeeRule {
+            flat: Decimal::ZERO,
+            percentage: dec!(0.01),
+            min: Some(Decimal::ONE),
+            max: Some(Decimal::TEN),
+        };
+
+        assert_eq!(rule.fee_for(dec!(50), &Precision::default()), Decimal::ONE);
+        assert_eq!(rule.fee_for(dec!(500), &Precision::default()), dec!(5));
+        assert_eq!(
+            rule.fee_for(dec!(5000), &Precision::default()),
+            Decimal::TEN
+        );
+    }
+
+    #[test]
+    fn test_fee_rule_rounding() {
+        let rule = FeeRule {
+            percentage: dec!(0.015),
+            ..FeeRule::default()
+        };
+
+        assert_eq!(
+            rule.fee_for(dec!(0.123), &Precision::default()),
+            dec!(0.0018)
+        );
+        assert_eq!(
+            rule.fee_for(dec!(0.01), &Precision::default()),
+            dec!(0.0002)
+        );
+
+        let half_up_2 = Precision {
+            decimal_places: 2,
+            rounding: Rounding::HalfUp,
+            strict: false,
+        };
+        assert_eq!(rule.fee_for(dec!(1), &half_up_2), dec!(0.02));
+        assert_eq!(rule.fee_for(dec!(0.123), &half_up_2), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_fee_schedule_without_rules() {
+        let schedule = FeeSchedule::default();
+        assert_eq!(
+            schedule.fee_for(FeeKind::Withdrawal, dec!(100), &Precision::default()),
+            Decimal::ZERO
+        );
+        assert_eq!(
+            schedule.fee_for(FeeKind::Chargeback, dec!(100), &Precision::default()),
+            Decimal::ZERO
+        );
+    }
+
+    #[test]
+    fn test_fee_schedule_from_csv() {
+        let csv_data = "kind,flat,percentage,min,max
+deposit,,0.001,,
+withdrawal,0.5,0.01,,2
+chargeback,15,,,";
+
+        let schedule = FeeSchedule::from_csv(csv_data.as_bytes()).unwrap();
+
+        assert_eq!(
+            schedule.deposit,
+            Some(FeeRule {
+                percentage: dec!(0.001),
+                ..FeeRule::default()
+            })
+        );
+        assert_eq!(
+            schedule.withdrawal,
+            Some(FeeRule {
+                flat: dec!(0.5),
+                percentage: dec!(0.01),
+                min: None,
+                max: Some(dec!(2)),
+            })
+        );
+        assert_eq!(schedule.chargeback, Some(FeeRule::flat(dec!(15))));
+    }
+
+    #[test]
+    fn test_fee_schedule_from_csv_invalid_kind() {
+        let csv_data = "kind,flat\ntransfer,1";
+        assert!(FeeSchedule::from_csv(csv_data.as_bytes()).is_err());
+    }
+}
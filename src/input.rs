@@ -0,0 +1,81 @@
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Path that stands for standard input.
+pub const STDIN_PATH: &str = "-";
+
+/// Opens a transaction file, or standard input for `-`, decompressing it if its extension is
+/// `.gz` or `.zst`.
+pub fn open_input(path: &Path) -> io::Result<Box<dyn io::Read + Send>> {
+    if path.as_os_str() == STDIN_PATH {
+        return Ok(Box::new(io::stdin()));
+    }
+
+    let file = File::open(path)?;
+    decompress(path, file)
+}
+
+/// Wraps the input in a decoder matching the extension of its path, if any.
+pub fn decompress(
+    path: &Path,
+    input: impl io::Read + Send + 'static,
+) -> io::Result<Box<dyn io::Read + Send>> {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    Ok(match extension {
+        // Gzip files may be made of several members, e.g. when shards are concatenated
+        Some("gz") => Box::new(MultiGzDecoder::new(input)),
+        Some("zst" | "zstd") => Box::new(zstd::Decoder::new(input)?),
+        _ => Box::new(input),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::{Cursor, Read, Write};
+
+    const CSV_DATA: &str = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+
+    fn read_all(path: &str, data: Vec<u8>) -> String {
+        let mut output = String::new();
+        decompress(Path::new(path), Cursor::new(data))
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_decompress_plain() {
+        assert_eq!(read_all("input.csv", CSV_DATA.into()), CSV_DATA);
+    }
+
+    #[test]
+    fn test_decompress_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(CSV_DATA.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(read_all("input.csv.gz", compressed), CSV_DATA);
+    }
+
+    #[test]
+    fn test_decompress_zstd() {
+        let compressed = zstd::encode_all(CSV_DATA.as_bytes(), 0).unwrap();
+
+        assert_eq!(read_all("input.csv.zst", compressed), CSV_DATA);
+    }
+
+    #[test]
+    fn test_decompress_invalid_gzip() {
+        let mut output = String::new();
+        let result = decompress(Path::new("input.csv.gz"), Cursor::new(CSV_DATA))
+            .unwrap()
+            .read_to_string(&mut output);
+        assert!(result.is_err());
+    }
+}
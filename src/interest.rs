@@ -0,0 +1,335 @@
+use crate::domain::{ClientId, TransactionId, serialize_decimal};
+use crate::precision::Precision;
+use rust_decimal::Decimal;
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Calendar date, used to measure interest periods.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Date {
+    year: i32,
+    month: u32,
+    day: u32,
+}
+
+impl Date {
+    /// Returns `None` if the day doesn't exist in the given month.
+    pub fn new(year: i32, month: u32, day: u32) -> Option<Self> {
+        if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return None;
+        }
+
+        Some(Self { year, month, day })
+    }
+
+    /// Days since 1970-01-01, see <https://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+    fn days_since_epoch(&self) -> i64 {
+        let year = i64::from(self.year) - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = i64::from(self.month);
+        let day_of_year =
+            (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+}
+
+impl Display for Date {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl FromStr for Date {
+    type Err = ParseDateError;
+
+    /// Parses a `YYYY-MM-DD` date.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseDateError(format!("invalid date {s:?}, expected YYYY-MM-DD"));
+        let mut parts = s.splitn(3, '-');
+        let mut next = || parts.next().ok_or_else(error);
+        let (year, month, day) = (next()?, next()?, next()?);
+
+        Date::new(
+            year.parse().map_err(|_| error())?,
+            month.parse().map_err(|_| error())?,
+            day.parse().map_err(|_| error())?,
+        )
+        .ok_or_else(error)
+    }
+}
+
+/// Reason why a date or an interest period couldn't be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseDateError(String);
+
+impl Display for ParseDateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ParseDateError {}
+
+impl Serialize for Date {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+fn is_leap_year(year: i32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Period interest is accrued for, from `start` included to `end` excluded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct InterestPeriod {
+    pub start: Date,
+    pub end: Date,
+}
+
+impl FromStr for InterestPeriod {
+    type Err = ParseDateError;
+
+    /// Parses a `YYYY-MM-DD..YYYY-MM-DD` period, with the end excluded.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((start, end)) = s.split_once("..") else {
+            return Err(ParseDateError(format!(
+                "invalid period {s:?}, expected YYYY-MM-DD..YYYY-MM-DD"
+            )));
+        };
+        let period = InterestPeriod {
+            start: start.parse()?,
+            end: end.parse()?,
+        };
+        if period.end <= period.start {
+            return Err(ParseDateError(format!(
+                "invalid period {s:?}, the end must be after the start"
+            )));
+        }
+
+        Ok(period)
+    }
+}
+
+/// How the days of a period are counted, and how many days make a year.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum DayCount {
+    /// Actual days over a 360 day year
+    Actual360,
+    /// Actual days over a 365 day year, leap years included
+    #[default]
+    Actual365,
+    /// 30E/360, every month counts as 30 days over a 360 day year
+    Thirty360,
+}
+
+impl DayCount {
+    pub fn days(&self, period: &InterestPeriod) -> i64 {
+        let (start, end) = (period.start, period.end);
+        match self {
+            DayCount::Actual360 | DayCount::Actual365 => {
+                end.days_since_epoch() - start.days_since_epoch()
+            }
+            DayCount::Thirty360 => {
+                360 * (i64::from(end.year) - i64::from(start.year))
+                    + 30 * (i64::from(end.month) - i64::from(start.month))
+                    + (i64::from(end.day.min(30)) - i64::from(start.day.min(30)))
+            }
+        }
+    }
+
+    pub fn days_in_year(&self) -> i64 {
+        match self {
+            DayCount::Actual360 | DayCount::Thirty360 => 360,
+            DayCount::Actual365 => 365,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterestConfig {
+    /// Yearly rate of clients without their own, as a fraction, e.g. `0.05` for 5%
+    pub default_rate: Decimal,
+    pub per_client: HashMap<ClientId, Decimal>,
+    pub day_count: DayCount,
+    /// Whether held funds earn interest too
+    pub include_held: bool,
+}
+
+impl Default for InterestConfig {
+    fn default() -> Self {
+        Self {
+            default_rate: Decimal::ZERO,
+            per_client: HashMap::new(),
+            day_count: DayCount::default(),
+            include_held: false,
+        }
+    }
+}
+
+impl InterestConfig {
+    pub fn rate_for(&self, client_id: ClientId) -> Decimal {
+        self.per_client
+            .get(&client_id)
+            .copied()
+            .unwrap_or(self.default_rate)
+    }
+
+    /// Computes the interest earned by a balance over the period, rounded to the precision of the
+    /// engine. Balances below zero earn nothing.
+    pub fn interest_for(
+        &self,
+        client_id: ClientId,
+        balance: Decimal,
+        period: &InterestPeriod,
+        precision: &Precision,
+    ) -> Decimal {
+        if balance <= Decimal::ZERO {
+            return Decimal::ZERO;
+        }
+
+        // Dividing last keeps the result exact for as long as possible
+        let days = Decimal::from(self.day_count.days(period).max(0));
+        let days_in_year = Decimal::from(self.day_count.days_in_year());
+        let interest = balance
+            .checked_mul(self.rate_for(client_id))
+            .and_then(|interest| interest.checked_mul(days))
+            .and_then(|interest| interest.checked_div(days_in_year))
+            .unwrap_or(Decimal::ZERO)
+            .max(Decimal::ZERO);
+        precision.round(interest)
+    }
+}
+
+/// Interest posted to a client, booked separately from its transactions.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct InterestEntry {
+    pub client: ClientId,
+    /// Interest transaction generated for the posting
+    pub tx: TransactionId,
+    pub start: Date,
+    pub end: Date,
+    /// Written with its own scale, see [`Precision::round_padded`]
+    #[serde(serialize_with = "serialize_decimal")]
+    pub amount: Decimal,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::precision::Rounding;
+    use rust_decimal::dec;
+
+    fn period(start: (i32, u32, u32), end: (i32, u32, u32)) -> InterestPeriod {
+        InterestPeriod {
+            start: Date::new(start.0, start.1, start.2).unwrap(),
+            end: Date::new(end.0, end.1, end.2).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_date_validation() {
+        assert!(Date::new(2024, 2, 29).is_some());
+        assert!(Date::new(2023, 2, 29).is_none());
+        assert!(Date::new(2024, 13, 1).is_none());
+        assert!(Date::new(2024, 4, 31).is_none());
+        assert_eq!(Date::new(2024, 3, 5).unwrap().to_string(), "2024-03-05");
+    }
+
+    #[test]
+    fn test_parse_period() {
+        assert_eq!(
+            "2024-01-01..2024-02-01".parse(),
+            Ok(period((2024, 1, 1), (2024, 2, 1)))
+        );
+        for invalid in [
+            "2024-01-01",
+            "2024-01-01..2024-02-30",
+            "2024-1..2024-02-01",
+            "2024-02-01..2024-01-01",
+        ] {
+            assert!(invalid.parse::<InterestPeriod>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_day_counts() {
+        let january = period((2024, 1, 1), (2024, 2, 1));
+        assert_eq!(DayCount::Actual365.days(&january), 31);
+        assert_eq!(DayCount::Thirty360.days(&january), 30);
+
+        let february = period((2024, 2, 1), (2024, 3, 1));
+        assert_eq!(DayCount::Actual360.days(&february), 29);
+        assert_eq!(DayCount::Thirty360.days(&february), 30);
+
+        let leap_year = period((2024, 1, 1), (2025, 1, 1));
+        assert_eq!(DayCount::Actual365.days(&leap_year), 366);
+        assert_eq!(DayCount::Thirty360.days(&leap_year), 360);
+
+        let month_end = period((2024, 1, 31), (2024, 3, 31));
+        assert_eq!(DayCount::Thirty360.days(&month_end), 60);
+    }
+
+    #[test]
+    fn test_interest_for() {
+        let precision = Precision::default();
+        let config = InterestConfig {
+            default_rate: dec!(0.05),
+            per_client: HashMap::from([(ClientId::new(2), dec!(0.1))]),
+            day_count: DayCount::Actual360,
+            ..InterestConfig::default()
+        };
+        let january = period((2024, 1, 1), (2024, 2, 1));
+
+        // 1000 * 0.05 * 31 / 360 = 4.30555...
+        assert_eq!(
+            config.interest_for(ClientId::new(1), dec!(1000), &january, &precision),
+            dec!(4.3056)
+        );
+        assert_eq!(
+            config.interest_for(ClientId::new(2), dec!(1000), &january, &precision),
+            dec!(8.6111)
+        );
+        assert_eq!(
+            config.interest_for(ClientId::new(1), dec!(-1000), &january, &precision),
+            Decimal::ZERO
+        );
+    }
+
+    #[test]
+    fn test_interest_rounding() {
+        let config = InterestConfig {
+            default_rate: dec!(0.0365),
+            ..InterestConfig::default()
+        };
+        let one_day = period((2024, 1, 1), (2024, 1, 2));
+        let mut precision = Precision::default();
+
+        // 0.5 * 0.0365 / 365 = 0.00005, exactly half way
+        assert_eq!(
+            config.interest_for(ClientId::new(1), dec!(0.5), &one_day, &precision),
+            Decimal::ZERO
+        );
+        precision.rounding = Rounding::HalfUp;
+        assert_eq!(
+            config.interest_for(ClientId::new(1), dec!(0.5), &one_day, &precision),
+            dec!(0.0001)
+        );
+    }
+}
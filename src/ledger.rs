@@ -0,0 +1,318 @@
+use crate::domain::{ClientId, TransactionId};
+use crate::fees::FeeKind;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// Account of the double-entry journal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LedgerAccount {
+    ClientAvailable(ClientId),
+    ClientHeld(ClientId),
+    /// Money entering and leaving the system, through deposits, withdrawals, refunds and chargebacks
+    HouseClearing,
+    HouseFees,
+    /// Interest paid out to clients
+    HouseInterest,
+}
+
+/// What produced a journal entry.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EntryKind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Authorize,
+    Capture,
+    Void,
+    HoldExpiry,
+    Refund,
+    Fee(FeeKind),
+    Interest,
+}
+
+impl LedgerAccount {
+    /// Client owning the account, if it isn't a house account.
+    pub fn client(&self) -> Option<ClientId> {
+        match self {
+            Self::ClientAvailable(client_id) | Self::ClientHeld(client_id) => Some(*client_id),
+            Self::HouseClearing | Self::HouseFees | Self::HouseInterest => None,
+        }
+    }
+}
+
+/// Movement of an amount on a single account. Positive amounts are credits and negative amounts
+/// debits, so client accounts have the same balance as the client.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Posting {
+    pub account: LedgerAccount,
+    pub amount: Decimal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    pub kind: EntryKind,
+    /// Transaction the entry was posted for, if any
+    pub tx: Option<TransactionId>,
+    pub postings: Vec<Posting>,
+}
+
+impl JournalEntry {
+    /// Entry moving `amount` out of the `from` account and into the `to` account.
+    pub fn transfer(
+        kind: EntryKind,
+        tx: Option<TransactionId>,
+        from: LedgerAccount,
+        to: LedgerAccount,
+        amount: Decimal,
+    ) -> Self {
+        Self {
+            kind,
+            tx,
+            postings: vec![
+                Posting {
+                    account: from,
+                    amount: -amount,
+                },
+                Posting {
+                    account: to,
+                    amount,
+                },
+            ],
+        }
+    }
+
+    /// Whether debits and credits cancel each other out.
+    pub fn is_balanced(&self) -> bool {
+        self.postings
+            .iter()
+            .try_fold(Decimal::ZERO, |total, posting| {
+                total.checked_add(posting.amount)
+            })
+            == Some(Decimal::ZERO)
+    }
+}
+
+/// Posting an entry would overflow the balance of one of its accounts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LedgerOverflow {
+    pub account: LedgerAccount,
+}
+
+impl Display for LedgerOverflow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The balance of account {:?} would overflow",
+            self.account
+        )
+    }
+}
+
+impl std::error::Error for LedgerOverflow {}
+
+/// Every entry posted so far, with the resulting balance of each account.
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    entries: Vec<JournalEntry>,
+    balances: HashMap<LedgerAccount, Decimal>,
+}
+
+impl Journal {
+    /// Records a balanced entry. Entries are built by the engine, so an unbalanced one is a bug.
+    ///
+    /// Nothing is recorded if any of the balances would overflow.
+    pub(crate) fn post(&mut self, entry: JournalEntry) -> Result<(), LedgerOverflow> {
+        debug_assert!(entry.is_balanced(), "unbalanced journal entry {entry:?}");
+
+        let balances = self.balances_after(&entry)?;
+        self.balances.extend(balances);
+        self.entries.push(entry);
+
+        Ok(())
+    }
+
+    /// Balances of the accounts touched by the entry once it is posted.
+    pub(crate) fn balances_after(
+        &self,
+        entry: &JournalEntry,
+    ) -> Result<HashMap<LedgerAccount, Decimal>, LedgerOverflow> {
+        let mut balances = HashMap::new();
+        for posting in &entry.postings {
+            let balance = *balances
+                .entry(posting.account)
+                .or_insert_with(|| self.balance(posting.account));
+            let updated = balance.checked_add(posting.amount).ok_or(LedgerOverflow {
+                account: posting.account,
+            })?;
+            balances.insert(posting.account, updated);
+        }
+
+        Ok(balances)
+    }
+
+    /// Removes every entry after the first `len` ones. The balances are recomputed from the
+    /// remaining entries, which were all posted before without overflowing.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        if len >= self.entries.len() {
+            return;
+        }
+
+        self.entries.truncate(len);
+        self.balances.clear();
+        for entry in &self.entries {
+            for posting in &entry.postings {
+                let balance = self.balances.entry(posting.account).or_default();
+                *balance += posting.amount;
+            }
+        }
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    pub fn balance(&self, account: LedgerAccount) -> Decimal {
+        self.balances.get(&account).copied().unwrap_or_default()
+    }
+
+    /// Sum of the balances of every account, which is zero as long as the books are consistent.
+    /// `None` if the sum overflows.
+    pub fn trial_balance(&self) -> Option<Decimal> {
+        self.balances
+            .values()
+            .try_fold(Decimal::ZERO, |total, balance| total.checked_add(*balance))
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.trial_balance() == Some(Decimal::ZERO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_transfer_is_balanced() {
+        let entry = JournalEntry::transfer(
+            EntryKind::Deposit,
+            Some(TransactionId::new(1)),
+            LedgerAccount::HouseClearing,
+            LedgerAccount::ClientAvailable(ClientId::new(1)),
+            dec!(12.5),
+        );
+
+        assert!(entry.is_balanced());
+        assert_eq!(entry.postings[0].amount, dec!(-12.5));
+        assert_eq!(entry.postings[1].amount, dec!(12.5));
+    }
+
+    #[test]
+    fn test_journal_balances() {
+        let client = ClientId::new(1);
+        let mut journal = Journal::default();
+
+        journal
+            .post(JournalEntry::transfer(
+                EntryKind::Deposit,
+                Some(TransactionId::new(1)),
+                LedgerAccount::HouseClearing,
+                LedgerAccount::ClientAvailable(client),
+                Decimal::TEN,
+            ))
+            .unwrap();
+        journal
+            .post(JournalEntry::transfer(
+                EntryKind::Dispute,
+                Some(TransactionId::new(1)),
+                LedgerAccount::ClientAvailable(client),
+                LedgerAccount::ClientHeld(client),
+                dec!(4),
+            ))
+            .unwrap();
+
+        assert_eq!(journal.entries().len(), 2);
+        assert_eq!(
+            journal.balance(LedgerAccount::ClientAvailable(client)),
+            dec!(6)
+        );
+        assert_eq!(journal.balance(LedgerAccount::ClientHeld(client)), dec!(4));
+        assert_eq!(journal.balance(LedgerAccount::HouseClearing), -Decimal::TEN);
+        assert_eq!(journal.balance(LedgerAccount::HouseFees), Decimal::ZERO);
+        assert!(journal.is_balanced());
+    }
+
+    #[test]
+    fn test_journal_truncate() {
+        let client = ClientId::new(1);
+        let mut journal = Journal::default();
+
+        journal
+            .post(JournalEntry::transfer(
+                EntryKind::Deposit,
+                Some(TransactionId::new(1)),
+                LedgerAccount::HouseClearing,
+                LedgerAccount::ClientAvailable(client),
+                Decimal::TEN,
+            ))
+            .unwrap();
+        journal
+            .post(JournalEntry::transfer(
+                EntryKind::Withdrawal,
+                Some(TransactionId::new(2)),
+                LedgerAccount::ClientAvailable(client),
+                LedgerAccount::HouseClearing,
+                dec!(4),
+            ))
+            .unwrap();
+        journal.truncate(1);
+
+        assert_eq!(journal.entries().len(), 1);
+        assert_eq!(
+            journal.balance(LedgerAccount::ClientAvailable(client)),
+            Decimal::TEN
+        );
+        assert_eq!(journal.balance(LedgerAccount::HouseClearing), -Decimal::TEN);
+        assert!(journal.is_balanced());
+    }
+
+    #[test]
+    fn test_journal_post_overflow() {
+        let client = ClientId::new(1);
+        let mut journal = Journal::default();
+        journal
+            .post(JournalEntry::transfer(
+                EntryKind::Deposit,
+                Some(TransactionId::new(1)),
+                LedgerAccount::HouseClearing,
+                LedgerAccount::ClientAvailable(client),
+                Decimal::MAX,
+            ))
+            .unwrap();
+
+        let result = journal.post(JournalEntry::transfer(
+            EntryKind::Deposit,
+            Some(TransactionId::new(2)),
+            LedgerAccount::HouseClearing,
+            LedgerAccount::ClientAvailable(ClientId::new(2)),
+            Decimal::ONE,
+        ));
+
+        assert_eq!(
+            result,
+            Err(LedgerOverflow {
+                account: LedgerAccount::HouseClearing
+            })
+        );
+        assert_eq!(journal.entries().len(), 1);
+        assert_eq!(
+            journal.balance(LedgerAccount::ClientAvailable(ClientId::new(2))),
+            Decimal::ZERO
+        );
+        assert_eq!(journal.trial_balance(), Some(Decimal::ZERO));
+    }
+}
@@ -1,3 +1,20 @@
+#[cfg(feature = "async")]
+pub mod async_engine;
+pub mod clock;
 pub mod csv;
 pub mod domain;
 pub mod engine;
+pub mod events;
+pub mod fees;
+pub mod input;
+pub mod interest;
+pub mod ledger;
+pub mod limits;
+pub mod precision;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod report;
+pub mod risk;
+pub mod shared;
+#[cfg(test)]
+mod test_utils;
@@ -0,0 +1,366 @@
+use crate::clock::Timestamp;
+use crate::domain::ClientId;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::io;
+
+const DAY_IN_SECONDS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    pub max_withdrawal: Option<Decimal>,
+    /// Maximum total withdrawn over any rolling 24 hour period
+    pub daily_withdrawal_total: Option<Decimal>,
+    pub max_deposits: Option<u32>,
+    /// Length of the rolling window `max_deposits` applies to, in seconds
+    pub deposit_window: u64,
+    /// Approved credit line, how far below zero withdrawals may take the available balance
+    pub overdraft: Option<Decimal>,
+}
+
+/// Limits that apply to every client, unless the client has its own.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LimitConfig {
+    pub default: Limits,
+    pub per_client: HashMap<ClientId, Limits>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LimitRow {
+    client: Option<ClientId>,
+    max_withdrawal: Option<Decimal>,
+    daily_withdrawal_total: Option<Decimal>,
+    max_deposits: Option<u32>,
+    deposit_window: Option<u64>,
+    overdraft: Option<Decimal>,
+}
+
+/// Reason why a limits file was refused.
+#[derive(Debug)]
+pub enum LimitConfigError {
+    Csv(csv::Error),
+    /// `max_deposits` is set without a positive `deposit_window`, which would never count any deposit
+    MissingDepositWindow {
+        line: u64,
+    },
+}
+
+impl Display for LimitConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitConfigError::Csv(e) => write!(f, "{e}"),
+            LimitConfigError::MissingDepositWindow { line } => write!(
+                f,
+                "line {line}: max_deposits requires a positive deposit_window"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitConfigError {}
+
+impl From<csv::Error> for LimitConfigError {
+    fn from(e: csv::Error) -> Self {
+        LimitConfigError::Csv(e)
+    }
+}
+
+impl LimitConfig {
+    pub fn limits_for(&self, client_id: ClientId) -> &Limits {
+        self.per_client.get(&client_id).unwrap_or(&self.default)
+    }
+
+    pub fn overdraft_for(&self, client_id: ClientId) -> Decimal {
+        self.limits_for(client_id).overdraft.unwrap_or_default()
+    }
+
+    pub fn has_overdrafts(&self) -> bool {
+        self.default.overdraft.is_some()
+            || self
+                .per_client
+                .values()
+                .any(|limits| limits.overdraft.is_some())
+    }
+
+    /// Reads limits from a CSV with the
+    /// `client,max_withdrawal,daily_withdrawal_total,max_deposits,deposit_window,overdraft` columns.
+    ///
+    /// A row with an empty `client` sets the default limits. A client's own row replaces the
+    /// default limits entirely, and empty columns mean no limit. `max_deposits` requires a
+    /// positive `deposit_window`.
+    pub fn from_csv(input: impl io::Read) -> Result<Self, LimitConfigError> {
+        let mut csv_reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(input);
+
+        let mut config = Self::default();
+        let mut record = csv::StringRecord::new();
+        while csv_reader.read_record(&mut record)? {
+            let row: LimitRow = record.deserialize(Some(csv_reader.headers()?))?;
+            if row.max_deposits.is_some() && row.deposit_window.unwrap_or_default() == 0 {
+                let line = record.position().map_or(0, csv::Position::line);
+                return Err(LimitConfigError::MissingDepositWindow { line });
+            }
+            let limits = Limits {
+                max_withdrawal: row.max_withdrawal,
+                daily_withdrawal_total: row.daily_withdrawal_total,
+                max_deposits: row.max_deposits,
+                deposit_window: row.deposit_window.unwrap_or_default(),
+                overdraft: row.overdraft,
+            };
+
+            match row.client {
+                Some(client_id) => {
+                    config.per_client.insert(client_id, limits);
+                }
+                None => config.default = limits,
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitKind {
+    SingleWithdrawal {
+        max: Decimal,
+    },
+    DailyWithdrawalTotal {
+        max: Decimal,
+        /// Amount already withdrawn in the last 24 hours
+        withdrawn: Decimal,
+    },
+    DepositVelocity {
+        max_deposits: u32,
+        window: u64,
+    },
+}
+
+impl Display for LimitKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitKind::SingleWithdrawal { max } => write!(f, "maximum withdrawal of {max}"),
+            LimitKind::DailyWithdrawalTotal { max, withdrawn } => write!(
+                f,
+                "daily withdrawal total of {max}, with {withdrawn} already withdrawn"
+            ),
+            LimitKind::DepositVelocity {
+                max_deposits,
+                window,
+            } => write!(
+                f,
+                "maximum of {max_deposits} deposits every {window} seconds"
+            ),
+        }
+    }
+}
+
+/// Recent deposits and withdrawals of a client, as needed by the velocity checks.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ClientActivity {
+    withdrawals: VecDeque<(Timestamp, Decimal)>,
+    deposits: VecDeque<Timestamp>,
+}
+
+impl ClientActivity {
+    pub(crate) fn check_withdrawal(
+        &mut self,
+        limits: &Limits,
+        amount: Decimal,
+        now: Timestamp,
+    ) -> Result<(), LimitKind> {
+        if let Some(max) = limits.max_withdrawal
+            && amount > max
+        {
+            return Err(LimitKind::SingleWithdrawal { max });
+        }
+
+        if let Some(max) = limits.daily_withdrawal_total {
+            self.expire_withdrawals(now);
+            let withdrawn = self
+                .withdrawals
+                .iter()
+                .fold(Decimal::ZERO, |total, (_, amount)| {
+                    total.saturating_add(*amount)
+                });
+            if withdrawn.saturating_add(amount) > max {
+                return Err(LimitKind::DailyWithdrawalTotal { max, withdrawn });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn check_deposit(
+        &mut self,
+        limits: &Limits,
+        now: Timestamp,
+    ) -> Result<(), LimitKind> {
+        if let Some(max_deposits) = limits.max_deposits {
+            self.expire_deposits(now, limits.deposit_window);
+            if self.deposits.len() >= max_deposits as usize {
+                return Err(LimitKind::DepositVelocity {
+                    max_deposits,
+                    window: limits.deposit_window,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn record_withdrawal(&mut self, amount: Decimal, now: Timestamp) {
+        self.withdrawals.push_back((now, amount));
+    }
+
+    pub(crate) fn record_deposit(&mut self, now: Timestamp) {
+        self.deposits.push_back(now);
+    }
+
+    fn expire_withdrawals(&mut self, now: Timestamp) {
+        while self
+            .withdrawals
+            .front()
+            .is_some_and(|(timestamp, _)| timestamp.saturating_add(DAY_IN_SECONDS) <= now)
+        {
+            self.withdrawals.pop_front();
+        }
+    }
+
+    fn expire_deposits(&mut self, now: Timestamp, window: u64) {
+        while self
+            .deposits
+            .front()
+            .is_some_and(|timestamp| timestamp.saturating_add(window) <= now)
+        {
+            self.deposits.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_limit_config_from_csv() {
+        let csv_data =
+            "client,max_withdrawal,daily_withdrawal_total,max_deposits,deposit_window,overdraft
+,100,500,,,
+2,1000,,5,3600,50";
+
+        let config = LimitConfig::from_csv(csv_data.as_bytes()).unwrap();
+
+        let default = Limits {
+            max_withdrawal: Some(dec!(100)),
+            daily_withdrawal_total: Some(dec!(500)),
+            max_deposits: None,
+            deposit_window: 0,
+            overdraft: None,
+        };
+        assert_eq!(config.default, default);
+        assert_eq!(config.limits_for(ClientId::new(1)), &default);
+        assert_eq!(
+            config.limits_for(ClientId::new(2)),
+            &Limits {
+                max_withdrawal: Some(dec!(1000)),
+                daily_withdrawal_total: None,
+                max_deposits: Some(5),
+                deposit_window: 3600,
+                overdraft: Some(dec!(50)),
+            }
+        );
+        assert!(config.has_overdrafts());
+        assert_eq!(config.overdraft_for(ClientId::new(1)), Decimal::ZERO);
+        assert_eq!(config.overdraft_for(ClientId::new(2)), dec!(50));
+    }
+
+    #[test]
+    fn test_limit_config_from_csv_invalid_row() {
+        let csv_data = "client,max_withdrawal\n1,abc";
+        assert!(LimitConfig::from_csv(csv_data.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_limit_config_from_csv_missing_deposit_window() {
+        for csv_data in [
+            "client,max_deposits\n1,5",
+            "client,max_deposits,deposit_window\n1,5,\n",
+            "client,max_deposits,deposit_window\n,1,60\n2,5,0",
+        ] {
+            assert!(matches!(
+                LimitConfig::from_csv(csv_data.as_bytes()),
+                Err(LimitConfigError::MissingDepositWindow { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_daily_withdrawal_total_is_rolling() {
+        let limits = Limits {
+            daily_withdrawal_total: Some(dec!(100)),
+            ..Limits::default()
+        };
+        let mut activity = ClientActivity::default();
+
+        activity.record_withdrawal(dec!(60), 0);
+        assert!(activity.check_withdrawal(&limits, dec!(40), 10).is_ok());
+        activity.record_withdrawal(dec!(40), 10);
+
+        assert_eq!(
+            activity.check_withdrawal(&limits, dec!(1), DAY_IN_SECONDS - 1),
+            Err(LimitKind::DailyWithdrawalTotal {
+                max: dec!(100),
+                withdrawn: dec!(100),
+            })
+        );
+        assert!(
+            activity
+                .check_withdrawal(&limits, dec!(60), DAY_IN_SECONDS)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_deposit_velocity() {
+        let limits = Limits {
+            max_deposits: Some(2),
+            deposit_window: 60,
+            ..Limits::default()
+        };
+        let mut activity = ClientActivity::default();
+
+        for now in [0, 30] {
+            assert!(activity.check_deposit(&limits, now).is_ok());
+            activity.record_deposit(now);
+        }
+        assert!(activity.check_deposit(&limits, 59).is_err());
+        assert!(activity.check_deposit(&limits, 60).is_ok());
+    }
+
+    #[test]
+    fn test_large_windows_do_not_overflow() {
+        let limits = Limits {
+            daily_withdrawal_total: Some(dec!(100)),
+            max_deposits: Some(1),
+            deposit_window: u64::MAX,
+            ..Limits::default()
+        };
+        let mut activity = ClientActivity::default();
+
+        activity.record_withdrawal(dec!(60), u64::MAX - 10);
+        activity.record_deposit(10);
+        assert!(
+            activity
+                .check_withdrawal(&limits, dec!(60), u64::MAX - 5)
+                .is_err()
+        );
+        assert!(activity.check_deposit(&limits, u64::MAX - 5).is_err());
+    }
+}
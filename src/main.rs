@@ -1,24 +1,299 @@
 use anyhow::Context;
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, value_parser};
 use payments_engine::csv;
-use payments_engine::engine::PaymentsEngine;
+use payments_engine::csv::{OutputOptions, OutputOrder};
+use payments_engine::engine::{IdNamespace, NegativeBalancePolicy, PaymentsEngine};
+use payments_engine::fees::FeeSchedule;
+use payments_engine::input::open_input;
+use payments_engine::interest::{InterestConfig, InterestPeriod};
+use payments_engine::limits::LimitConfig;
+use payments_engine::precision::{DEFAULT_DECIMAL_PLACES, Precision, Rounding};
+use payments_engine::report::TransactionStats;
+use rust_decimal::Decimal;
 use std::fs::File;
+use std::io;
 use std::io::stdout;
-use std::path::PathBuf;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    arg_required_else_help = true
+)]
 pub struct Cli {
-    pub csv_path: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Inputs of `process`, which runs when no subcommand is given
+    pub csv_paths: Vec<PathBuf>,
+    #[command(flatten)]
+    pub process_options: ProcessOptions,
 }
 
-fn main() -> anyhow::Result<()> {
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Processes the transactions and prints the resulting accounts
+    Process(ProcessArgs),
+    /// Parses the transactions and reports malformed rows, without processing them
+    Validate {
+        #[arg(required = true)]
+        csv_paths: Vec<PathBuf>,
+    },
+    /// Processes the transactions and reports row counts, rejections and amount totals
+    Stats {
+        #[arg(required = true)]
+        csv_paths: Vec<PathBuf>,
+        #[command(flatten)]
+        engine: EngineArgs,
+    },
+    /// Compares two account output files
+    Diff { left: PathBuf, right: PathBuf },
+}
+
+#[derive(Args, Debug)]
+pub struct ProcessArgs {
+    /// Transaction files, processed in order. `-` reads from stdin, and `.gz` and `.zst` files are
+    /// decompressed
+    #[arg(required = true)]
+    pub csv_paths: Vec<PathBuf>,
+    #[command(flatten)]
+    pub options: ProcessOptions,
+}
+
+#[derive(Args, Debug)]
+pub struct ProcessOptions {
+    /// Number of threads used to parse the input file
+    #[arg(long, default_value = "1")]
+    pub threads: NonZeroUsize,
+    /// Fails without writing any output if a row is malformed, has an unknown type or lacks a
+    /// required amount, instead of skipping it. Only works with a single thread
+    #[arg(long)]
+    pub strict: bool,
+    #[command(flatten)]
+    pub engine: EngineArgs,
+    /// Writes every fee charged to this file
+    #[arg(long)]
+    pub fee_statement: Option<PathBuf>,
+    /// Writes the locked accounts that owe money to this file
+    #[arg(long)]
+    pub collections: Option<PathBuf>,
+    /// Yearly interest rate paid to every account, as a fraction, e.g. `0.05` for 5%
+    #[arg(long, requires = "accrue_interest")]
+    pub interest_rate: Option<Decimal>,
+    /// Credits interest for the `YYYY-MM-DD..YYYY-MM-DD` period, end excluded, once every input
+    /// has been processed
+    #[arg(long, requires = "interest_rate")]
+    pub accrue_interest: Option<InterestPeriod>,
+    /// Writes every interest posting to this file
+    #[arg(long, requires = "accrue_interest")]
+    pub interest_statement: Option<PathBuf>,
+    /// Order of the account rows
+    #[arg(long, value_enum, default_value_t)]
+    pub order: OutputOrder,
+}
+
+#[derive(Args, Debug)]
+pub struct EngineArgs {
+    /// CSV file with the `kind,flat,percentage,min,max` fee rules. Adds a `fees` column to the output
+    #[arg(long)]
+    pub fee_schedule: Option<PathBuf>,
+    /// CSV file with the default and per-client withdrawal and deposit limits
+    #[arg(long)]
+    pub limits: Option<PathBuf>,
+    /// Lets disputes hold funds the client no longer has, driving its available balance negative
+    #[arg(long)]
+    pub allow_negative_balance: bool,
+    /// Lets different clients use the same transaction IDs, instead of IDs being unique overall
+    #[arg(long)]
+    pub per_client_ids: bool,
+    /// Takes deposits, withdrawals and authorizations with the same type, client and amount as an
+    /// earlier one for retries of it, whatever their transaction IDs
+    #[arg(long, conflicts_with = "per_client_ids")]
+    pub content_hash_ids: bool,
+    /// Seconds after which authorized funds that were neither captured nor voided are released
+    #[arg(long)]
+    pub hold_expiry: Option<u64>,
+    /// Number of decimal places of the input amounts and the output balances
+    #[arg(long, default_value_t = DEFAULT_DECIMAL_PLACES, value_parser = value_parser!(u32).range(0..=28))]
+    pub precision: u32,
+    /// How amounts and balances with more decimal places than `--precision` are rounded
+    #[arg(long, value_enum, default_value_t)]
+    pub rounding: Rounding,
+    /// Rejects input amounts with more decimal places than `--precision` instead of rounding them
+    #[arg(long)]
+    pub strict_precision: bool,
+}
+
+fn main() -> anyhow::Result<ExitCode> {
     let args = Cli::parse();
-    let file = File::open(args.csv_path).context("Failed to open input file")?;
 
+    match args.command {
+        Some(Command::Process(args)) => process(&args.csv_paths, args.options),
+        Some(Command::Validate { csv_paths }) => validate(&csv_paths),
+        Some(Command::Stats { csv_paths, engine }) => stats(&csv_paths, engine),
+        Some(Command::Diff { left, right }) => diff(left, right),
+        // Without a subcommand the input is processed, like `process` does
+        None if !args.csv_paths.is_empty() => process(&args.csv_paths, args.process_options),
+        None => Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "at least one input CSV path is required",
+            )
+            .exit(),
+    }
+}
+
+fn build_engine(args: &EngineArgs) -> anyhow::Result<(PaymentsEngine, OutputOptions)> {
     let mut engine = PaymentsEngine::new();
-    csv::process_csv_transactions(&mut engine, file);
-    csv::print_account_records(&engine, stdout())?;
+    let mut output_options = OutputOptions::default();
+    if let Some(path) = &args.fee_schedule {
+        let fee_file = File::open(path).context("Failed to open fee schedule")?;
+        let fee_schedule = FeeSchedule::from_csv(fee_file).context("Invalid fee schedule")?;
+        engine = engine.with_fee_schedule(fee_schedule);
+        output_options.include_fees = true;
+    }
+    if let Some(path) = &args.limits {
+        let limits_file = File::open(path).context("Failed to open limits file")?;
+        let limits = LimitConfig::from_csv(limits_file).context("Invalid limits file")?;
+        output_options.include_overdraft = limits.has_overdrafts();
+        engine = engine.with_limits(limits);
+    }
+
+    if args.allow_negative_balance {
+        engine = engine.with_negative_balance_policy(NegativeBalancePolicy::AllowNegative);
+    }
+
+    if args.per_client_ids {
+        engine = engine.with_id_namespace(IdNamespace::PerClient);
+    }
+    if args.content_hash_ids {
+        engine = engine.with_id_namespace(IdNamespace::ContentHash);
+    }
+
+    if let Some(seconds) = args.hold_expiry {
+        engine = engine.with_hold_expiry(seconds);
+    }
+
+    engine = engine.with_precision(Precision {
+        decimal_places: args.precision,
+        rounding: args.rounding,
+        strict: args.strict_precision,
+    });
+
+    Ok((engine, output_options))
+}
+
+fn open(path: &Path) -> anyhow::Result<Box<dyn io::Read + Send>> {
+    open_input(path).with_context(|| format!("Failed to open input file {}", path.display()))
+}
+
+fn process(csv_paths: &[PathBuf], args: ProcessOptions) -> anyhow::Result<ExitCode> {
+    if args.strict && args.threads.get() > 1 {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--strict can't be used with more than one thread",
+            )
+            .exit();
+    }
+    let (mut engine, mut output_options) = build_engine(&args.engine)?;
+    output_options.order = args.order;
+    if let Some(rate) = args.interest_rate {
+        engine = engine.with_interest(InterestConfig {
+            default_rate: rate,
+            ..InterestConfig::default()
+        });
+    }
+
+    for path in csv_paths {
+        let input = open(path)?;
+        if args.strict {
+            csv::process_csv_transactions_strict(&mut engine, input)
+                .with_context(|| format!("Invalid input file {}", path.display()))?;
+        } else if args.threads.get() > 1 {
+            csv::process_csv_transactions_parallel(&mut engine, input, args.threads)
+                .with_context(|| format!("Failed to read input file {}", path.display()))?;
+        } else {
+            csv::process_csv_transactions(&mut engine, input)
+                .with_context(|| format!("Failed to read input file {}", path.display()))?;
+        }
+    }
+    engine.expire_holds();
+    if let Some(period) = args.accrue_interest {
+        engine.accrue_interest(period);
+    }
+    csv::print_account_records_with_options(&engine, stdout(), &output_options)?;
+
+    if let Some(path) = &args.fee_statement {
+        let statement = File::create(path).context("Failed to create fee statement")?;
+        csv::print_fee_entries(&engine, statement)?;
+    }
+
+    if let Some(path) = &args.interest_statement {
+        let statement = File::create(path).context("Failed to create interest statement")?;
+        csv::print_interest_entries(&engine, statement)?;
+    }
+
+    if let Some(path) = &args.collections {
+        let collections = File::create(path).context("Failed to create collections file")?;
+        csv::print_collections(&engine, collections)?;
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn validate(csv_paths: &[PathBuf]) -> anyhow::Result<ExitCode> {
+    let mut malformed_rows = 0;
+    for path in csv_paths {
+        let errors = csv::validate_csv_transactions(open(path)?)
+            .with_context(|| format!("Failed to read input file {}", path.display()))?;
+        for e in &errors {
+            println!("{}: {e}", path.display());
+        }
+        malformed_rows += errors.len();
+    }
+
+    if malformed_rows == 0 {
+        println!("No malformed rows");
+        Ok(ExitCode::SUCCESS)
+    } else {
+        println!("{malformed_rows} malformed rows");
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+fn stats(csv_paths: &[PathBuf], engine_args: EngineArgs) -> anyhow::Result<ExitCode> {
+    let (mut engine, _) = build_engine(&engine_args)?;
+
+    let mut stats = TransactionStats::default();
+    for path in csv_paths {
+        csv::collect_csv_stats(&mut engine, open(path)?, &mut stats)
+            .with_context(|| format!("Failed to read input file {}", path.display()))?;
+    }
+    print!("{}", stats.display(engine.precision()));
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn diff(left: PathBuf, right: PathBuf) -> anyhow::Result<ExitCode> {
+    let left = File::open(left).context("Failed to open left file")?;
+    let right = File::open(right).context("Failed to open right file")?;
+
+    let diffs = csv::diff_account_records(left, right).context("Failed to read account files")?;
+    for diff in &diffs {
+        println!("{diff}");
+    }
 
-    Ok(())
+    // Like `diff`, exit with a failure when the files differ
+    if diffs.is_empty() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
 }
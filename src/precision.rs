@@ -0,0 +1,118 @@
+use clap::ValueEnum;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+/// Number of decimal places amounts are kept at unless configured otherwise.
+pub const DEFAULT_DECIMAL_PLACES: u32 = 4;
+
+/// How amounts with more decimal places than allowed are rounded.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, ValueEnum)]
+pub enum Rounding {
+    /// Half to even, e.g. 0.00125 becomes 0.0012
+    #[default]
+    Bankers,
+    /// Half away from zero, e.g. 0.00125 becomes 0.0013
+    HalfUp,
+    /// Drops the extra decimal places, e.g. 0.00129 becomes 0.0012
+    Truncate,
+}
+
+impl Rounding {
+    pub fn strategy(self) -> RoundingStrategy {
+        match self {
+            Rounding::Bankers => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::Truncate => RoundingStrategy::ToZero,
+        }
+    }
+}
+
+/// Number of decimal places of the amounts read and the balances written by the engine.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Precision {
+    pub decimal_places: u32,
+    pub rounding: Rounding,
+    /// Refuses input amounts with more decimal places instead of rounding them
+    pub strict: bool,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Self {
+            decimal_places: DEFAULT_DECIMAL_PLACES,
+            rounding: Rounding::default(),
+            strict: false,
+        }
+    }
+}
+
+impl Precision {
+    /// Rounds the value to at most the number of decimal places.
+    pub fn round(&self, value: Decimal) -> Decimal {
+        value.round_dp_with_strategy(self.decimal_places, self.rounding.strategy())
+    }
+
+    /// Rounds the value and pads it with zeros to exactly the number of decimal places, as written
+    /// to the output.
+    pub fn round_padded(&self, value: Decimal) -> Decimal {
+        let mut rounded = self.round(value);
+        rounded.rescale(self.decimal_places);
+        rounded
+    }
+
+    /// Whether the value has more significant decimal places than allowed. Trailing zeros don't count.
+    pub fn is_exceeded_by(&self, value: Decimal) -> bool {
+        value.normalize().scale() > self.decimal_places
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    fn precision(decimal_places: u32, rounding: Rounding) -> Precision {
+        Precision {
+            decimal_places,
+            rounding,
+            strict: false,
+        }
+    }
+
+    #[test]
+    fn test_round() {
+        let bankers = precision(4, Rounding::Bankers);
+        assert_eq!(bankers.round(dec!(0.00125)).to_string(), "0.0012");
+        assert_eq!(bankers.round(dec!(0.00135)).to_string(), "0.0014");
+        assert_eq!(bankers.round(dec!(-0.00125)).to_string(), "-0.0012");
+
+        let half_up = precision(4, Rounding::HalfUp);
+        assert_eq!(half_up.round(dec!(0.00125)).to_string(), "0.0013");
+        assert_eq!(half_up.round(dec!(-0.00125)).to_string(), "-0.0013");
+
+        let truncate = precision(4, Rounding::Truncate);
+        assert_eq!(truncate.round(dec!(0.00129)).to_string(), "0.0012");
+        assert_eq!(truncate.round(dec!(-0.00129)).to_string(), "-0.0012");
+    }
+
+    #[test]
+    fn test_round_padded() {
+        let precision_2 = precision(2, Rounding::Bankers);
+        assert_eq!(precision_2.round(dec!(1.5)).to_string(), "1.5");
+        assert_eq!(precision_2.round_padded(dec!(1.5)).to_string(), "1.50");
+        assert_eq!(precision_2.round_padded(dec!(1.555)).to_string(), "1.56");
+        assert_eq!(
+            precision(0, Rounding::Bankers)
+                .round_padded(dec!(2.5))
+                .to_string(),
+            "2"
+        );
+    }
+
+    #[test]
+    fn test_is_exceeded_by() {
+        let precision = precision(2, Rounding::Bankers);
+        assert!(!precision.is_exceeded_by(dec!(1.25)));
+        assert!(!precision.is_exceeded_by(dec!(1.2500)));
+        assert!(precision.is_exceeded_by(dec!(1.255)));
+    }
+}
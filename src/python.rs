@@ -0,0 +1,285 @@
+use crate::csv::{
+    parse_transaction_type, process_csv_transactions, process_csv_transactions_strict,
+};
+use crate::domain::{Amount, ClientId, TransactionId, TransactionRow};
+use crate::engine::{self, IdNamespace, NegativeBalancePolicy, PaymentsEngine};
+use crate::input::open_input;
+use crate::precision::{Precision, Rounding};
+use clap::ValueEnum;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyOSError, PyValueError};
+use pyo3::prelude::*;
+use rust_decimal::Decimal;
+use std::path::PathBuf;
+
+create_exception!(
+    payments_engine,
+    ProcessingError,
+    PyException,
+    "Raised when the engine rejects a transaction, e.g. for insufficient funds."
+);
+
+impl From<engine::ProcessingError> for PyErr {
+    fn from(error: engine::ProcessingError) -> Self {
+        ProcessingError::new_err(error.to_string())
+    }
+}
+
+/// Python wrapper of [`PaymentsEngine`]. Amounts and balances are `decimal.Decimal`s.
+#[pyclass(name = "PaymentsEngine", module = "payments_engine")]
+pub struct PyPaymentsEngine {
+    engine: PaymentsEngine,
+}
+
+#[pymethods]
+impl PyPaymentsEngine {
+    #[new]
+    #[pyo3(signature = (
+        *,
+        decimal_places = Precision::default().decimal_places,
+        rounding = "bankers",
+        strict_precision = false,
+        per_client_ids = false,
+        content_hash_ids = false,
+        allow_negative_balance = false,
+    ))]
+    fn new(
+        decimal_places: u32,
+        rounding: &str,
+        strict_precision: bool,
+        per_client_ids: bool,
+        content_hash_ids: bool,
+        allow_negative_balance: bool,
+    ) -> PyResult<Self> {
+        if per_client_ids && content_hash_ids {
+            return Err(PyValueError::new_err(
+                "per_client_ids and content_hash_ids can't be combined",
+            ));
+        }
+        if decimal_places > Decimal::MAX_SCALE {
+            return Err(PyValueError::new_err(format!(
+                "decimal_places must be at most {}",
+                Decimal::MAX_SCALE
+            )));
+        }
+        let rounding = Rounding::from_str(rounding, true).map_err(PyValueError::new_err)?;
+
+        let mut engine = PaymentsEngine::new().with_precision(Precision {
+            decimal_places,
+            rounding,
+            strict: strict_precision,
+        });
+        if per_client_ids {
+            engine = engine.with_id_namespace(IdNamespace::PerClient);
+        }
+        if content_hash_ids {
+            engine = engine.with_id_namespace(IdNamespace::ContentHash);
+        }
+        if allow_negative_balance {
+            engine = engine.with_negative_balance_policy(NegativeBalancePolicy::AllowNegative);
+        }
+
+        Ok(Self { engine })
+    }
+
+    /// Processes a transaction, raising `ProcessingError` if the engine rejects it.
+    #[pyo3(signature = (tx_type, client, tx, amount = None))]
+    fn process_transaction(
+        &mut self,
+        tx_type: &str,
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    ) -> PyResult<()> {
+        let tx_type =
+            parse_transaction_type(tx_type).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let amount = amount
+            .map(Amount::new)
+            .transpose()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let row = TransactionRow {
+            tx_type,
+            client: ClientId::new(client),
+            tx: TransactionId::new(tx),
+            amount,
+        };
+
+        Ok(self.engine.process_transaction(row.into())?)
+    }
+
+    /// Processes the transactions of a CSV file, which may be gzip or zstd compressed. Rejected
+    /// transactions are reported on stderr and skipped. With `strict`, the whole file is refused
+    /// with a `ValueError` if any row is malformed. Raises `OSError` if the file can't be read.
+    /// Other Python threads keep running while the file is processed.
+    #[pyo3(signature = (path, *, strict = false))]
+    fn process_csv(&mut self, py: Python<'_>, path: PathBuf, strict: bool) -> PyResult<()> {
+        let engine = &mut self.engine;
+        py.detach(|| {
+            let input = open_input(&path).map_err(|e| {
+                PyOSError::new_err(format!("Failed to open input file {}: {e}", path.display()))
+            })?;
+            if strict {
+                process_csv_transactions_strict(engine, input)
+                    .map_err(|e| PyValueError::new_err(e.to_string()))?;
+            } else {
+                process_csv_transactions(engine, input).map_err(|e| {
+                    PyOSError::new_err(format!("Failed to read input file {}: {e}", path.display()))
+                })?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Returns the accounts sorted by client, with balances rounded to the engine's precision.
+    fn client_accounts(&self) -> Vec<PyClientAccount> {
+        let precision = self.engine.precision();
+        let mut accounts: Vec<_> = self
+            .engine
+            .client_accounts()
+            .iter()
+            .map(|(client_id, account)| PyClientAccount {
+                client: client_id.value(),
+                available: precision.round_padded(account.available_balance),
+                held: precision.round_padded(account.held_balance),
+                total: precision.round_padded(account.total()),
+                locked: account.locked,
+                flagged: account.flagged,
+            })
+            .collect();
+        accounts.sort_by_key(|account| account.client);
+
+        accounts
+    }
+}
+
+/// Snapshot of a client account, as returned by `PaymentsEngine.client_accounts`.
+#[pyclass(name = "ClientAccount", module = "payments_engine", frozen, get_all)]
+#[derive(Debug)]
+pub struct PyClientAccount {
+    client: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    flagged: bool,
+}
+
+#[pymethods]
+impl PyClientAccount {
+    fn __repr__(&self) -> String {
+        format!(
+            "ClientAccount(client={}, available={}, held={}, total={}, locked={}, flagged={})",
+            self.client,
+            self.available,
+            self.held,
+            self.total,
+            if self.locked { "True" } else { "False" },
+            if self.flagged { "True" } else { "False" },
+        )
+    }
+}
+
+#[pymodule]
+fn payments_engine(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyPaymentsEngine>()?;
+    m.add_class::<PyClientAccount>()?;
+    m.add("ProcessingError", m.py().get_type::<ProcessingError>())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+    use pyo3::wrap_pymodule;
+    use std::ffi::CStr;
+
+    fn run_python(code: &CStr) {
+        run_python_with_locals(code, |_| {});
+    }
+
+    /// Runs the code with the module and the locals set by `set_locals` in scope.
+    fn run_python_with_locals(code: &CStr, set_locals: impl FnOnce(&Bound<'_, PyDict>)) {
+        Python::initialize();
+        Python::attach(|py| {
+            let locals = PyDict::new(py);
+            locals
+                .set_item("payments_engine", wrap_pymodule!(payments_engine)(py))
+                .unwrap();
+            set_locals(&locals);
+            if let Err(e) = py.run(code, None, Some(&locals)) {
+                panic!("Python code failed: {e}");
+            }
+        });
+    }
+
+    #[test]
+    fn test_process_transaction() {
+        run_python(
+            c"
+from decimal import Decimal
+
+engine = payments_engine.PaymentsEngine()
+engine.process_transaction('deposit', client=2, tx=1, amount=Decimal('1.5'))
+engine.process_transaction('deposit', 1, 2, Decimal('2'))
+engine.process_transaction('withdrawal', 1, 3, Decimal('0.5'))
+
+accounts = engine.client_accounts()
+assert [account.client for account in accounts] == [1, 2]
+assert accounts[0].available == Decimal('1.5')
+assert isinstance(accounts[0].total, Decimal)
+assert str(accounts[1].held) == '0.0000'
+assert not accounts[1].locked
+",
+        );
+    }
+
+    #[test]
+    fn test_process_transaction_errors() {
+        run_python(
+            c"
+from decimal import Decimal
+
+engine = payments_engine.PaymentsEngine(decimal_places=2)
+try:
+    engine.process_transaction('withdrawal', 1, 1, Decimal('5'))
+    assert False
+except payments_engine.ProcessingError as e:
+    assert str(e) == 'Transaction 1 of client 1 requires 5 but only 0 is available'
+
+for args in [('transfer', 1, 2, Decimal('1')), ('deposit', 1, 2, Decimal('-1'))]:
+    try:
+        engine.process_transaction(*args)
+        assert False
+    except ValueError:
+        pass
+",
+        );
+    }
+
+    #[test]
+    fn test_process_csv() {
+        let path = std::env::temp_dir().join(format!(
+            "payments_engine_python_test_{}.csv",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\nwithdrawal,1,3,1.5\n",
+        )
+        .unwrap();
+
+        run_python_with_locals(
+            c"
+engine = payments_engine.PaymentsEngine()
+engine.process_csv(path)
+accounts = engine.client_accounts()
+assert [str(account.available) for account in accounts] == ['1.0000', '2.0000']
+",
+            |locals| locals.set_item("path", &path).unwrap(),
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
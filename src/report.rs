@@ -0,0 +1,218 @@
+use crate::domain::{ClientId, Transaction, TransactionType};
+use crate::engine::ProcessingError;
+use crate::precision::Precision;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// Counts and totals over a transaction file, as collected while processing it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TransactionStats {
+    pub rows: u64,
+    pub malformed_rows: u64,
+    pub rows_by_type: BTreeMap<TransactionType, u64>,
+    pub clients: HashSet<ClientId>,
+    /// Rejected transactions, by [`ProcessingError::kind`]
+    pub rejections: BTreeMap<&'static str, u64>,
+    /// Sum of the amounts of every applied transaction with an amount, by transaction type, as
+    /// rounded by the engine. Rejected transactions are only counted in `rejections`, and retries
+    /// of applied ones aren't counted again
+    pub amount_totals: BTreeMap<TransactionType, Decimal>,
+}
+
+impl TransactionStats {
+    /// Counts a row holding `transaction`. `applied` is the transaction as the engine applied it,
+    /// or `None` if the row didn't change the engine, e.g. because it was rejected or a retry.
+    pub fn record(
+        &mut self,
+        transaction: &Transaction,
+        result: &Result<(), ProcessingError>,
+        applied: Option<&Transaction>,
+    ) {
+        self.rows += 1;
+        *self
+            .rows_by_type
+            .entry(transaction.tx_type.clone())
+            .or_default() += 1;
+        self.clients.insert(transaction.client);
+
+        if let Err(e) = result {
+            *self.rejections.entry(e.kind()).or_default() += 1;
+        }
+        if let Some(applied) = applied
+            && let Some(amount) = applied.amount
+        {
+            let total = self
+                .amount_totals
+                .entry(applied.tx_type.clone())
+                .or_default();
+            *total = total.saturating_add(amount.value());
+        }
+    }
+
+    pub fn record_malformed(&mut self) {
+        self.rows += 1;
+        self.malformed_rows += 1;
+    }
+
+    pub fn rejected_rows(&self) -> u64 {
+        self.rejections.values().sum()
+    }
+
+    /// Displays the stats, with the amount totals rounded to the precision.
+    pub fn display<'a>(&'a self, precision: &'a Precision) -> StatsDisplay<'a> {
+        StatsDisplay {
+            stats: self,
+            precision,
+        }
+    }
+}
+
+/// Output of [`TransactionStats::display`].
+pub struct StatsDisplay<'a> {
+    stats: &'a TransactionStats,
+    precision: &'a Precision,
+}
+
+impl Display for StatsDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let stats = self.stats;
+        writeln!(f, "rows: {}", stats.rows)?;
+        writeln!(f, "malformed rows: {}", stats.malformed_rows)?;
+        writeln!(f, "distinct clients: {}", stats.clients.len())?;
+        writeln!(f, "rows by type:")?;
+        for (tx_type, count) in &stats.rows_by_type {
+            writeln!(f, "  {tx_type}: {count}")?;
+        }
+        writeln!(f, "applied amount totals:")?;
+        for (tx_type, total) in &stats.amount_totals {
+            let total = self.precision.round_padded(*total);
+            writeln!(f, "  {tx_type}: {total}")?;
+        }
+        writeln!(f, "rejected rows: {}", stats.rejected_rows())?;
+        for (reason, count) in &stats.rejections {
+            writeln!(f, "  {reason}: {count}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// One of the two files given to [`crate::csv::diff_account_records`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiffSide {
+    Left,
+    Right,
+}
+
+impl Display for DiffSide {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiffSide::Left => write!(f, "left"),
+            DiffSide::Right => write!(f, "right"),
+        }
+    }
+}
+
+/// Difference between two account output files, for a single client.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccountDiff {
+    /// Client only present in the right file
+    Added { client: String, record: String },
+    /// Client only present in the left file
+    Removed { client: String, record: String },
+    /// Column whose value differs between the two files
+    Changed {
+        client: String,
+        column: String,
+        left: String,
+        right: String,
+    },
+    /// Extra row of a client already present in the same file. Only the first row of a client is
+    /// compared
+    Duplicate {
+        client: String,
+        side: DiffSide,
+        record: String,
+    },
+}
+
+impl Display for AccountDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccountDiff::Added { record, .. } => write!(f, "+ {record}"),
+            AccountDiff::Removed { record, .. } => write!(f, "- {record}"),
+            AccountDiff::Changed {
+                client,
+                column,
+                left,
+                right,
+            } => write!(f, "~ client {client}: {column} {left} -> {right}"),
+            AccountDiff::Duplicate {
+                client,
+                side,
+                record,
+            } => write!(f, "! client {client} duplicated in {side}: {record}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::TransactionId;
+    use crate::domain::TransactionType::{Deposit, Withdrawal};
+    use crate::test_utils::create_transaction;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_transaction_stats() {
+        let mut stats = TransactionStats::default();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(dec!(1.5)));
+        stats.record(&deposit, &Ok(()), Some(&deposit));
+        let deposit = create_transaction(Deposit, 2, 2, Some(dec!(2)));
+        stats.record(&deposit, &Ok(()), Some(&deposit));
+        // A retry doesn't apply anything
+        stats.record(&deposit, &Ok(()), None);
+        let withdrawal = create_transaction(Withdrawal, 1, 4, Some(dec!(0.5)));
+        stats.record(&withdrawal, &Ok(()), Some(&withdrawal));
+        let withdrawal = create_transaction(Withdrawal, 1, 3, Some(dec!(5)));
+        stats.record(
+            &withdrawal,
+            &Err(ProcessingError::InsufficientFunds {
+                client: ClientId::new(1),
+                tx: TransactionId::new(3),
+                requested: dec!(5),
+                available: dec!(1.5),
+            }),
+            None,
+        );
+        stats.record_malformed();
+
+        assert_eq!(stats.rows, 6);
+        assert_eq!(stats.malformed_rows, 1);
+        assert_eq!(stats.clients.len(), 2);
+        assert_eq!(stats.rows_by_type[&Deposit], 3);
+        assert_eq!(stats.amount_totals[&Deposit], dec!(3.5));
+        // The rejected withdrawal isn't part of the total
+        assert_eq!(stats.amount_totals[&Withdrawal], dec!(0.5));
+        assert_eq!(stats.rejected_rows(), 1);
+        assert_eq!(stats.rejections["InsufficientFunds"], 1);
+        assert_eq!(
+            stats.display(&Precision::default()).to_string(),
+            "rows: 6
+malformed rows: 1
+distinct clients: 2
+rows by type:
+  deposit: 3
+  withdrawal: 2
+applied amount totals:
+  deposit: 3.5000
+  withdrawal: 0.5000
+rejected rows: 1
+  InsufficientFunds: 1
+"
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use crate::clock::Timestamp;
+use crate::domain::{ClientId, Transaction, TransactionId, TransactionType};
+use crate::engine::ClientAccount;
+use rust_decimal::Decimal;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+
+/// What a rule does when it is triggered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RiskAction {
+    /// Let the transaction through, but flag the account for review
+    Flag,
+    /// Reject the transaction
+    Deny,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskOutcome {
+    Pass,
+    Flag { reason: String },
+    Deny { reason: String },
+}
+
+impl RiskOutcome {
+    fn triggered(action: RiskAction, reason: String) -> Self {
+        match action {
+            RiskAction::Flag => RiskOutcome::Flag { reason },
+            RiskAction::Deny => RiskOutcome::Deny { reason },
+        }
+    }
+}
+
+impl Display for RiskOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RiskOutcome::Pass => write!(f, "passed"),
+            RiskOutcome::Flag { reason } => write!(f, "flagged: {reason}"),
+            RiskOutcome::Deny { reason } => write!(f, "denied: {reason}"),
+        }
+    }
+}
+
+/// What the engine knows about a client's past behaviour, kept up to date for the risk rules.
+#[derive(Debug, Clone, Default)]
+pub struct RiskProfile {
+    pub last_deposit_at: Option<Timestamp>,
+    pub dispute_count: u32,
+}
+
+pub struct RiskContext<'a> {
+    pub account: &'a ClientAccount,
+    pub profile: &'a RiskProfile,
+    pub now: Timestamp,
+}
+
+/// Check run against every deposit and withdrawal before it is applied.
+pub trait RiskRule: Send + Sync {
+    /// Name recorded in the audit trail and in rejections
+    fn name(&self) -> &str;
+
+    fn evaluate(&self, transaction: &Transaction, context: &RiskContext) -> RiskOutcome;
+}
+
+/// A rule that was triggered by a transaction, whether it was let through or not.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub rule: String,
+    pub outcome: RiskOutcome,
+}
+
+/// Triggers on deposits and withdrawals above a threshold.
+pub struct LargeAmountRule {
+    pub threshold: Decimal,
+    pub action: RiskAction,
+}
+
+impl RiskRule for LargeAmountRule {
+    fn name(&self) -> &str {
+        "large_amount"
+    }
+
+    fn evaluate(&self, transaction: &Transaction, _context: &RiskContext) -> RiskOutcome {
+        match transaction.amount {
+            Some(amount) if amount.value() > self.threshold => RiskOutcome::triggered(
+                self.action,
+                format!("amount {} is above {}", amount.value(), self.threshold),
+            ),
+            _ => RiskOutcome::Pass,
+        }
+    }
+}
+
+/// Triggers on withdrawals made shortly after a deposit.
+pub struct RapidWithdrawalRule {
+    /// Seconds after a deposit during which withdrawals trigger the rule
+    pub window: u64,
+    pub action: RiskAction,
+}
+
+impl RiskRule for RapidWithdrawalRule {
+    fn name(&self) -> &str {
+        "rapid_withdrawal"
+    }
+
+    fn evaluate(&self, transaction: &Transaction, context: &RiskContext) -> RiskOutcome {
+        if transaction.tx_type != TransactionType::Withdrawal {
+            return RiskOutcome::Pass;
+        }
+
+        match context.profile.last_deposit_at {
+            Some(deposited_at) if context.now.saturating_sub(deposited_at) < self.window => {
+                RiskOutcome::triggered(
+                    self.action,
+                    format!(
+                        "withdrawal {} seconds after a deposit",
+                        context.now.saturating_sub(deposited_at)
+                    ),
+                )
+            }
+            _ => RiskOutcome::Pass,
+        }
+    }
+}
+
+/// Triggers on every transaction of clients that opened too many disputes.
+pub struct DisputeCountRule {
+    pub max_disputes: u32,
+    pub action: RiskAction,
+}
+
+impl RiskRule for DisputeCountRule {
+    fn name(&self) -> &str {
+        "dispute_count"
+    }
+
+    fn evaluate(&self, _transaction: &Transaction, context: &RiskContext) -> RiskOutcome {
+        if context.profile.dispute_count > self.max_disputes {
+            RiskOutcome::triggered(
+                self.action,
+                format!(
+                    "client opened {} disputes, more than {}",
+                    context.profile.dispute_count, self.max_disputes
+                ),
+            )
+        } else {
+            RiskOutcome::Pass
+        }
+    }
+}
+
+/// Denies every transaction of the listed clients.
+pub struct DenyListRule {
+    pub clients: HashSet<ClientId>,
+}
+
+impl RiskRule for DenyListRule {
+    fn name(&self) -> &str {
+        "deny_list"
+    }
+
+    fn evaluate(&self, transaction: &Transaction, _context: &RiskContext) -> RiskOutcome {
+        if self.clients.contains(&transaction.client) {
+            RiskOutcome::Deny {
+                reason: format!("client {} is deny-listed", transaction.client),
+            }
+        } else {
+            RiskOutcome::Pass
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::TransactionType::{Deposit, Withdrawal};
+    use crate::test_utils::create_transaction;
+    use rust_decimal::dec;
+
+    fn evaluate(
+        rule: &dyn RiskRule,
+        transaction: &Transaction,
+        profile: RiskProfile,
+    ) -> RiskOutcome {
+        let account = ClientAccount::default();
+        let context = RiskContext {
+            account: &account,
+            profile: &profile,
+            now: 1_000,
+        };
+        rule.evaluate(transaction, &context)
+    }
+
+    #[test]
+    fn test_large_amount_rule() {
+        let rule = LargeAmountRule {
+            threshold: dec!(100),
+            action: RiskAction::Flag,
+        };
+
+        let small = create_transaction(Deposit, 1, 1, Some(dec!(100)));
+        assert_eq!(
+            evaluate(&rule, &small, RiskProfile::default()),
+            RiskOutcome::Pass
+        );
+
+        let large = create_transaction(Withdrawal, 1, 2, Some(dec!(100.01)));
+        assert_eq!(
+            evaluate(&rule, &large, RiskProfile::default()),
+            RiskOutcome::Flag {
+                reason: "amount 100.01 is above 100".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_rapid_withdrawal_rule() {
+        let rule = RapidWithdrawalRule {
+            window: 60,
+            action: RiskAction::Deny,
+        };
+        let withdrawal = create_transaction(Withdrawal, 1, 1, Some(Decimal::ONE));
+        let deposit = create_transaction(Deposit, 1, 2, Some(Decimal::ONE));
+
+        let recent = RiskProfile {
+            last_deposit_at: Some(950),
+            dispute_count: 0,
+        };
+        assert!(matches!(
+            evaluate(&rule, &withdrawal, recent.clone()),
+            RiskOutcome::Deny { .. }
+        ));
+        assert_eq!(evaluate(&rule, &deposit, recent), RiskOutcome::Pass);
+
+        let old = RiskProfile {
+            last_deposit_at: Some(940),
+            dispute_count: 0,
+        };
+        assert_eq!(evaluate(&rule, &withdrawal, old), RiskOutcome::Pass);
+        assert_eq!(
+            evaluate(&rule, &withdrawal, RiskProfile::default()),
+            RiskOutcome::Pass
+        );
+    }
+
+    #[test]
+    fn test_dispute_count_rule() {
+        let rule = DisputeCountRule {
+            max_disputes: 2,
+            action: RiskAction::Deny,
+        };
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::ONE));
+
+        let profile = |dispute_count| RiskProfile {
+            last_deposit_at: None,
+            dispute_count,
+        };
+        assert_eq!(evaluate(&rule, &deposit, profile(2)), RiskOutcome::Pass);
+        assert!(matches!(
+            evaluate(&rule, &deposit, profile(3)),
+            RiskOutcome::Deny { .. }
+        ));
+    }
+
+    #[test]
+    fn test_deny_list_rule() {
+        let rule = DenyListRule {
+            clients: HashSet::from([ClientId::new(2)]),
+        };
+
+        let allowed = create_transaction(Deposit, 1, 1, Some(Decimal::ONE));
+        let denied = create_transaction(Deposit, 2, 2, Some(Decimal::ONE));
+        assert_eq!(
+            evaluate(&rule, &allowed, RiskProfile::default()),
+            RiskOutcome::Pass
+        );
+        assert_eq!(
+            evaluate(&rule, &denied, RiskProfile::default()),
+            RiskOutcome::Deny {
+                reason: "client 2 is deny-listed".to_string()
+            }
+        );
+    }
+}
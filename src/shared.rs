@@ -0,0 +1,443 @@
+use crate::domain::{ClientId, Transaction, TransactionId};
+use crate::engine::{
+    ClientAccount, ClientMismatchPolicy, IdNamespace, PaymentsEngine, ProcessingError,
+};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+const TRANSACTION_ID_SHARDS: usize = 64;
+
+/// Thread-safe engine that can be shared between threads, e.g. behind an `Arc` in a web server.
+///
+/// Transactions only ever affect their own client, so each client is backed by its own
+/// [`PaymentsEngine`] behind its own lock. Calls for different clients do not contend, except
+/// briefly when a client is seen for the first time.
+///
+/// Except with [`IdNamespace::PerClient`], transaction ids of deposits and withdrawals are still
+/// unique across all clients, as in [`PaymentsEngine`]. They are tracked in a sharded map so that
+/// claiming an id only locks a fraction of it.
+pub struct SharedPaymentsEngine {
+    clients: RwLock<HashMap<ClientId, Arc<Mutex<PaymentsEngine>>>>,
+    transaction_owners: Vec<Mutex<HashMap<TransactionId, ClientId>>>,
+    engine_factory: Box<dyn Fn() -> PaymentsEngine + Send + Sync>,
+}
+
+impl Default for SharedPaymentsEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SharedPaymentsEngine {
+    pub fn new() -> Self {
+        Self::with_engine_factory(PaymentsEngine::new)
+    }
+
+    /// Creates a shared engine whose per-client engines are built by `engine_factory`, e.g. to
+    /// configure a fee schedule or a client mismatch policy.
+    pub fn with_engine_factory(
+        engine_factory: impl Fn() -> PaymentsEngine + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            clients: RwLock::new(HashMap::new()),
+            transaction_owners: (0..TRANSACTION_ID_SHARDS)
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+            engine_factory: Box::new(engine_factory),
+        }
+    }
+
+    /// Applies the policy to every client engine, on top of the ones built by the engine factory.
+    pub fn with_client_mismatch_policy(mut self, policy: ClientMismatchPolicy) -> Self {
+        let engine_factory = self.engine_factory;
+        self.engine_factory =
+            Box::new(move || engine_factory().with_client_mismatch_policy(policy));
+        self
+    }
+
+    pub fn process_transaction(&self, transaction: Transaction) -> Result<(), ProcessingError> {
+        let client_engine = self.client_engine(transaction.client);
+        // Locks are always taken in client -> transaction id shard order
+        let mut client_engine = lock(&client_engine);
+        if client_engine.id_namespace() == IdNamespace::PerClient {
+            // Ids never refer to another client's transactions
+            return client_engine.process_transaction(transaction);
+        }
+
+        let mut owners = lock(&self.transaction_owners[shard_of(transaction.tx)]);
+
+        if !transaction.tx_type.is_standard_transaction() {
+            // The client's own engine only knows about its own transactions
+            if let Some(owner) = owners
+                .get(&transaction.tx)
+                .filter(|owner| **owner != transaction.client)
+            {
+                client_engine.open_account(transaction.client)?;
+                client_engine.record_client_mismatch(transaction.client);
+                return Err(ProcessingError::ClientMismatch {
+                    client: transaction.client,
+                    tx: transaction.tx,
+                    owner: *owner,
+                });
+            }
+
+            return client_engine.process_transaction(transaction);
+        }
+
+        if owners
+            .get(&transaction.tx)
+            .is_some_and(|owner| *owner != transaction.client)
+        {
+            return Err(ProcessingError::TransactionIdConflict {
+                client: transaction.client,
+                tx: transaction.tx,
+            });
+        }
+
+        // Rejected transactions keep their id too, as retries get their original outcome
+        let (client, tx) = (transaction.client, transaction.tx);
+        let result = client_engine.process_transaction(transaction);
+        owners.insert(tx, client);
+
+        result
+    }
+
+    /// Returns a consistent snapshot of all client accounts.
+    ///
+    /// Every client is locked while the snapshot is taken, so no transaction is ever half-visible.
+    pub fn client_accounts(&self) -> HashMap<ClientId, ClientAccount> {
+        let clients = self.clients.read().unwrap_or_else(|e| e.into_inner());
+
+        // Sorted so that concurrent snapshots always lock clients in the same order
+        let mut client_ids: Vec<&ClientId> = clients.keys().collect();
+        client_ids.sort_by_key(|client_id| client_id.value());
+
+        let guards: Vec<(ClientId, MutexGuard<PaymentsEngine>)> = client_ids
+            .into_iter()
+            .map(|client_id| (*client_id, lock(&clients[client_id])))
+            .collect();
+
+        guards
+            .iter()
+            .filter_map(|(client_id, engine)| {
+                engine
+                    .client_accounts()
+                    .get(client_id)
+                    .map(|account| (*client_id, account.clone()))
+            })
+            .collect()
+    }
+
+    pub fn client_account(&self, client_id: ClientId) -> Option<ClientAccount> {
+        let client_engine = self
+            .clients
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&client_id)
+            .cloned()?;
+
+        lock(&client_engine)
+            .client_accounts()
+            .get(&client_id)
+            .cloned()
+    }
+
+    /// Total of all fees collected by the house, across all clients.
+    pub fn house_fees(&self) -> Decimal {
+        self.clients
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .map(|client_engine| lock(client_engine).house_fees())
+            .fold(Decimal::ZERO, Decimal::saturating_add)
+    }
+
+    pub fn client_mismatch_count(&self, client_id: ClientId) -> u32 {
+        self.clients
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&client_id)
+            .map(|client_engine| lock(client_engine).client_mismatch_count(client_id))
+            .unwrap_or_default()
+    }
+
+    fn client_engine(&self, client_id: ClientId) -> Arc<Mutex<PaymentsEngine>> {
+        if let Some(engine) = self
+            .clients
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&client_id)
+        {
+            return Arc::clone(engine);
+        }
+
+        let mut clients = self.clients.write().unwrap_or_else(|e| e.into_inner());
+        Arc::clone(
+            clients
+                .entry(client_id)
+                .or_insert_with(|| Arc::new(Mutex::new((self.engine_factory)()))),
+        )
+    }
+}
+
+fn shard_of(tx: TransactionId) -> usize {
+    tx.value() as usize % TRANSACTION_ID_SHARDS
+}
+
+/// A panic while holding a lock cannot leave an account half-updated, as every balance change is
+/// only applied once all checks have passed, so poisoned locks are safe to keep using.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::TransactionType::{Chargeback, Deposit, Dispute, Withdrawal};
+    use crate::engine::{ClientMismatchAction, ClientMismatchPolicy};
+    use crate::fees::{FeeRule, FeeSchedule};
+    use crate::test_utils::create_transaction;
+    use rust_decimal::{Decimal, dec};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_shared_engine_matches_engine_semantics() {
+        let engine = SharedPaymentsEngine::new();
+
+        let transactions = vec![
+            create_transaction(Deposit, 1, 1, Some(Decimal::TEN)),
+            create_transaction(Withdrawal, 1, 2, Some(dec!(3))),
+            create_transaction(Deposit, 2, 3, Some(dec!(5))),
+            create_transaction(Dispute, 2, 3, None),
+            create_transaction(Chargeback, 2, 3, None),
+        ];
+        for tx in transactions {
+            engine.process_transaction(tx).unwrap();
+        }
+
+        let accounts = engine.client_accounts();
+        assert_eq!(accounts.len(), 2);
+        let account1 = accounts.get(&ClientId::new(1)).unwrap();
+        assert_eq!(account1.available_balance, dec!(7));
+        let account2 = accounts.get(&ClientId::new(2)).unwrap();
+        assert_eq!(account2.total(), Decimal::ZERO);
+        assert!(account2.locked);
+
+        let deposit = create_transaction(Deposit, 2, 4, Some(Decimal::ONE));
+        assert_eq!(
+            engine.process_transaction(deposit),
+            Err(ProcessingError::AccountLocked {
+                client: ClientId::new(2)
+            })
+        );
+    }
+
+    #[test]
+    fn test_shared_engine_duplicate_transaction_id_across_clients_conflict() {
+        let engine = SharedPaymentsEngine::new();
+
+        let deposit1 = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let deposit2 = create_transaction(Deposit, 2, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit1).unwrap();
+        let result = engine.process_transaction(deposit2);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::TransactionIdConflict {
+                client: ClientId::new(2),
+                tx: TransactionId::new(1)
+            })
+        );
+        assert!(engine.client_account(ClientId::new(2)).is_none());
+    }
+
+    #[test]
+    fn test_shared_engine_per_client_id_namespace() {
+        let engine = SharedPaymentsEngine::with_engine_factory(|| {
+            PaymentsEngine::new().with_id_namespace(IdNamespace::PerClient)
+        });
+
+        let deposit1 = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let deposit2 = create_transaction(Deposit, 2, 1, Some(Decimal::ONE));
+        engine.process_transaction(deposit1).unwrap();
+        engine.process_transaction(deposit2).unwrap();
+
+        let account2 = engine.client_account(ClientId::new(2)).unwrap();
+        assert_eq!(account2.available_balance, Decimal::ONE);
+    }
+
+    #[test]
+    fn test_shared_engine_dispute_other_client_transaction() {
+        let engine = SharedPaymentsEngine::new();
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        let dispute = create_transaction(Dispute, 2, 1, None);
+        engine.process_transaction(deposit).unwrap();
+        let result = engine.process_transaction(dispute);
+
+        assert_eq!(
+            result,
+            Err(ProcessingError::ClientMismatch {
+                client: ClientId::new(2),
+                tx: TransactionId::new(1),
+                owner: ClientId::new(1)
+            })
+        );
+        let account1 = engine.client_account(ClientId::new(1)).unwrap();
+        assert_eq!(account1.held_balance, Decimal::ZERO);
+        assert_eq!(engine.client_mismatch_count(ClientId::new(2)), 1);
+    }
+
+    #[test]
+    fn test_shared_engine_client_mismatch_policy() {
+        let engine =
+            SharedPaymentsEngine::new().with_client_mismatch_policy(ClientMismatchPolicy {
+                threshold: 2,
+                action: ClientMismatchAction::Lock,
+            });
+
+        let deposit = create_transaction(Deposit, 1, 1, Some(Decimal::TEN));
+        engine.process_transaction(deposit).unwrap();
+        for _ in 0..2 {
+            let dispute = create_transaction(Dispute, 2, 1, None);
+            let _ = engine.process_transaction(dispute);
+        }
+
+        assert!(engine.client_account(ClientId::new(2)).unwrap().locked);
+        assert!(!engine.client_account(ClientId::new(1)).unwrap().locked);
+    }
+
+    #[test]
+    fn test_shared_engine_unknown_client() {
+        let engine = SharedPaymentsEngine::new();
+        assert!(engine.client_account(ClientId::new(1)).is_none());
+        assert!(engine.client_accounts().is_empty());
+    }
+
+    #[test]
+    fn test_shared_engine_stress() {
+        const CLIENTS: u16 = 16;
+        const ROUNDS: u32 = 500;
+
+        let engine = SharedPaymentsEngine::new();
+        let done = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let writers: Vec<_> = (1..=CLIENTS)
+                .map(|client| {
+                    let engine = &engine;
+                    scope.spawn(move || {
+                        for round in 0..ROUNDS {
+                            let tx_id = (u32::from(client) * ROUNDS + round) * 2;
+                            let deposit =
+                                create_transaction(Deposit, client, tx_id, Some(Decimal::ONE));
+                            let withdrawal = create_transaction(
+                                Withdrawal,
+                                client,
+                                tx_id + 1,
+                                Some(Decimal::ONE),
+                            );
+                            engine.process_transaction(deposit).unwrap();
+                            engine.process_transaction(withdrawal).unwrap();
+                        }
+                        // Leave every client with a final balance equal to its id
+                        let deposit = create_transaction(
+                            Deposit,
+                            client,
+                            u32::MAX - u32::from(client),
+                            Some(Decimal::from(client)),
+                        );
+                        engine.process_transaction(deposit).unwrap();
+                    })
+                })
+                .collect();
+
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        while !done.load(Ordering::Acquire) {
+                            for (client_id, account) in engine.client_accounts() {
+                                assert!(account.available_balance >= Decimal::ZERO);
+                                assert!(
+                                    account.available_balance <= Decimal::ONE
+                                        || account.available_balance
+                                            == Decimal::from(client_id.value())
+                                );
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            done.store(true, Ordering::Release);
+            for reader in readers {
+                reader.join().unwrap();
+            }
+        });
+
+        let accounts = engine.client_accounts();
+        assert_eq!(accounts.len(), usize::from(CLIENTS));
+        for (client_id, account) in accounts {
+            assert_eq!(account.available_balance, Decimal::from(client_id.value()));
+            assert_eq!(account.held_balance, Decimal::ZERO);
+        }
+    }
+
+    #[test]
+    fn test_shared_engine_concurrent_duplicate_transaction_ids() {
+        let engine = SharedPaymentsEngine::new();
+
+        thread::scope(|scope| {
+            for client in 0..32u16 {
+                let engine = &engine;
+                scope.spawn(move || {
+                    for tx_id in 0..100 {
+                        let deposit =
+                            create_transaction(Deposit, client, tx_id, Some(Decimal::ONE));
+                        let result = engine.process_transaction(deposit);
+                        assert!(matches!(
+                            result,
+                            Ok(()) | Err(ProcessingError::TransactionIdConflict { .. })
+                        ));
+                    }
+                });
+            }
+        });
+
+        // Each transaction id must have been applied exactly once, whichever client won it
+        let total: Decimal = engine
+            .client_accounts()
+            .values()
+            .map(|account| account.available_balance)
+            .sum();
+        assert_eq!(total, dec!(100));
+    }
+
+    #[test]
+    fn test_shared_engine_house_fees() {
+        let engine = SharedPaymentsEngine::with_engine_factory(|| {
+            PaymentsEngine::new().with_fee_schedule(FeeSchedule {
+                deposit: None,
+                withdrawal: Some(FeeRule::flat(Decimal::ONE)),
+                chargeback: None,
+            })
+        });
+
+        for client in 1..=3 {
+            let tx_id = u32::from(client) * 2;
+            let deposit = create_transaction(Deposit, client, tx_id, Some(Decimal::TEN));
+            let withdrawal = create_transaction(Withdrawal, client, tx_id + 1, Some(Decimal::ONE));
+            engine.process_transaction(deposit).unwrap();
+            engine.process_transaction(withdrawal).unwrap();
+        }
+
+        assert_eq!(engine.house_fees(), dec!(3));
+        let account = engine.client_account(ClientId::new(1)).unwrap();
+        assert_eq!(account.available_balance, dec!(8));
+    }
+}
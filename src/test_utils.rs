@@ -0,0 +1,22 @@
+use crate::domain::{
+    Amount, ClientId, Transaction, TransactionId, TransactionStatus, TransactionType,
+};
+use rust_decimal::Decimal;
+
+pub fn create_transaction(
+    tx_type: TransactionType,
+    client: u16,
+    tx_id: u32,
+    amount: Option<Decimal>,
+) -> Transaction {
+    let amount = amount.map(|a| Amount::new(a).unwrap());
+    Transaction {
+        tx_type,
+        client: ClientId::new(client),
+        tx: TransactionId::new(tx_id),
+        amount,
+        submitted_amount: amount,
+        tx_status: TransactionStatus::Pending,
+        refunded: Decimal::ZERO,
+    }
+}